@@ -1,11 +1,13 @@
 #![feature(slice_patterns)]
 
+use std::collections::HashMap;
 use std::env;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
 use std::io::Write;
 use std::path::Path;
+use std::path::PathBuf;
 
 extern crate linked_hash_map;
 use linked_hash_map::LinkedHashMap;
@@ -14,49 +16,132 @@ extern crate itertools;
 use itertools::Itertools;
 
 
+/// Set to a directory with another version of the Unicode Character Database to build against
+/// it instead of the bundled `ucd`.
+const UCD_DIR_VAR: &str = "TYPEHOLDER_UCD_DIR";
+
+/// Set by cargo for the `script-extensions` feature, which makes scripts include the code points
+/// they share with others. The files it needs are not bundled, so it goes with `UCD_DIR_VAR`.
+const SCRIPT_EXTENSIONS_FEATURE_VAR: &str = "CARGO_FEATURE_SCRIPT_EXTENSIONS";
+
 fn main() {
     let out_dir = env::var("OUT_DIR").unwrap();
     let dest_path = Path::new(&out_dir).join("ucd.rs");
     let mut f = File::create(&dest_path).unwrap();
 
-    write(&mut f, generate_blocks_macro(read_lines("ucd/Blocks.txt")));
+    println!("cargo:rerun-if-env-changed={}", UCD_DIR_VAR);
+    let ucd_dir = PathBuf::from(env::var(UCD_DIR_VAR).unwrap_or(String::from("ucd")));
+    let script_extensions = env::var_os(SCRIPT_EXTENSIONS_FEATURE_VAR).is_some();
+    let mut names = vec!["Blocks.txt", "Scripts.txt"];
+    if script_extensions {
+        names.extend(&["ScriptExtensions.txt", "PropertyValueAliases.txt"]);
+    }
+    for name in names.iter() {
+        println!("cargo:rerun-if-changed={}", ucd_dir.join(name).display());
+    }
+
+    write(&mut f, generate_version(&ucd_dir.join("Blocks.txt")));
+    write(
+        &mut f,
+        generate_blocks_table(read_lines(&ucd_dir.join("Blocks.txt"))),
+    );
     write(
         &mut f,
-        generate_scripts_macro(read_lines("ucd/Scripts.txt")),
+        generate_scripts_tables(
+            read_lines(&ucd_dir.join("Scripts.txt")),
+            if script_extensions {
+                read_script_extensions(&ucd_dir)
+            } else {
+                vec![]
+            },
+        ),
     );
 }
 
-fn generate_blocks_macro(lines: Vec<String>) -> String {
-    let codes = lines
+/// The version comes from the first line of Blocks.txt, e.g. `# Blocks-10.0.0.txt`.
+fn generate_version(path: &Path) -> String {
+    let f = File::open(path).unwrap();
+    let first_line = BufReader::new(&f).lines().next().unwrap().unwrap();
+    let version = first_line
+        .trim_left_matches("# Blocks-")
+        .trim_right_matches(".txt");
+    format!(
+        "
+/// The Unicode version the block and script tables were generated from.
+pub const UNICODE_VERSION: &str = \"{}\";
+",
+        version
+    )
+}
+
+/// Code points used by several scripts, e.g. CJK punctuation, by the long name of each script
+/// as used in Scripts.txt. ScriptExtensions.txt names scripts by their short aliases, so it is
+/// read together with PropertyValueAliases.txt, both must be there.
+fn read_script_extensions(ucd_dir: &Path) -> Vec<(String, (String, String))> {
+    let extensions_path = ucd_dir.join("ScriptExtensions.txt");
+    let aliases_path = ucd_dir.join("PropertyValueAliases.txt");
+    if !extensions_path.exists() || !aliases_path.exists() {
+        panic!(
+            "The script-extensions feature needs ScriptExtensions.txt and \
+             PropertyValueAliases.txt in {}, point {} to a full copy of the UCD",
+            ucd_dir.display(),
+            UCD_DIR_VAR
+        );
+    }
+
+    // e.g. `sc ; Hani ; Han`
+    let long_names: HashMap<String, String> = read_lines(&aliases_path)
+        .iter()
+        .map(|l| l.split(';').map(str::trim).collect_vec())
+        .filter(|v| v.len() >= 3 && v[0] == "sc")
+        .map(|v| (v[1].to_string(), v[2].to_string()))
+        .collect();
+
+    // e.g. `3001..3003    ; Bopo Hang Hani Hira Kana Yiii`
+    read_lines(&extensions_path)
+        .iter()
+        .filter_map(|l| l.splitn(2, ";").map(str::trim).next_tuple::<(_, _)>())
+        .flat_map(|(range, names)| {
+            let v = range.splitn(2, "..").map(str::to_string).collect_vec();
+            let range = match &v[..] {
+                &[ref l, ref r] => (l.clone(), r.clone()),
+                &[ref x] => (x.clone(), x.clone()),
+                _ => unreachable!(),
+            };
+            names
+                .split_whitespace()
+                .filter_map(|x| long_names.get(x))
+                .map(|x| (x.clone(), range.clone()))
+                .collect_vec()
+        })
+        .collect()
+}
+
+fn generate_blocks_table(lines: Vec<String>) -> String {
+    let rows = lines
         .iter()
         .map(|l| {
             let pair: Vec<&str> = l.splitn(2, "; ").collect();
             let range: Vec<&str> = pair[0].splitn(2, "..").collect();
-            let name = pair[1];
-            format!(
-                "Range::Block {{ name: String::from(\"{}\"), code_points: (0x{}, 0x{}) }}",
-                name,
-                range[0],
-                range[1]
-            )
+            format!("(0x{}, 0x{}, \"{}\")", range[0], range[1], pair[1].trim())
         })
-        .join(&format!(",\n{}", " ".repeat(4 * 3)));
+        .join(",\n    ");
 
     format!(
         "
-macro_rules! unicode_blocks {{
-    () => (
-        vec![
-            {}
-        ];
-    )
-}}
+/// `(first, last, name)` of every block, sorted by code point.
+pub static BLOCKS: &[(u32, u32, &str)] = &[
+    {}
+];
 ",
-        codes
+        rows
     )
 }
 
-fn generate_scripts_macro(lines: Vec<String>) -> String {
+fn generate_scripts_tables(
+    lines: Vec<String>,
+    extensions: Vec<(String, (String, String))>,
+) -> String {
     let scripts: LinkedHashMap<&str, Vec<(&str, &str)>> = lines
         .iter()
         .filter_map(|l| l.splitn(2, ";").map(str::trim).next_tuple::<(_, _)>())
@@ -105,34 +190,88 @@ fn generate_scripts_macro(lines: Vec<String>) -> String {
                 })
                 .collect();
 
-            (*name, merged_ranges)
+            // shared code points join the script after merging, since they are in other
+            // scripts' ranges by definition
+            let extended_ranges = merged_ranges
+                .into_iter()
+                .chain(
+                    extensions
+                        .iter()
+                        .filter(|&&(ref script, _)| script == name)
+                        .map(|&(_, ref range)| range.clone()),
+                )
+                .sorted_by(|x, y| {
+                    i32::from_str_radix(&x.0, 16).unwrap().cmp(
+                        &i32::from_str_radix(&y.0, 16).unwrap(),
+                    )
+                })
+                .into_iter()
+                .coalesce(|x, y| {
+                    let x_end = i32::from_str_radix(&x.1, 16).unwrap();
+                    let y_start = i32::from_str_radix(&y.0, 16).unwrap();
+                    let y_end = i32::from_str_radix(&y.1, 16).unwrap();
+                    if y_start <= x_end + 1 {
+                        Ok((x.0, if y_end > x_end { y.1 } else { x.1 }))
+                    } else {
+                        Err((x, y))
+                    }
+                })
+                .collect();
+
+            (*name, extended_ranges)
         })
         .collect();
 
-    let codes = merged_scripts
+    let scripts_rows = merged_scripts
         .iter()
         .map(|(k, v)| {
             format!(
-                "Range::Script {{ name: String::from(\"{}\"), code_points: vec![{}] }}",
+                "(\"{}\", &[{}])",
                 k,
                 v.iter()
                     .map(|&(ref start, ref end)| format!("(0x{}, 0x{})", start, end))
                     .join(", ")
             )
         })
-        .join(&format!(",\n{}", " ".repeat(4 * 3)));
+        .join(",\n    ");
+
+    // lookups go by the Scripts.txt value alone, without extensions
+    let ranges_rows = scripts
+        .iter()
+        .flat_map(|(name, ranges)| ranges.iter().map(move |&(l, r)| (l, r, *name)))
+        .sorted_by(|x, y| {
+            i32::from_str_radix(x.0, 16).unwrap().cmp(
+                &i32::from_str_radix(y.0, 16).unwrap(),
+            )
+        })
+        .into_iter()
+        .coalesce(|x, y| {
+            let contiguous = i32::from_str_radix(x.1, 16).unwrap() + 1 ==
+                i32::from_str_radix(y.0, 16).unwrap();
+            if contiguous && x.2 == y.2 {
+                Ok((x.0, y.1, x.2))
+            } else {
+                Err((x, y))
+            }
+        })
+        .map(|(l, r, name)| format!("(0x{}, 0x{}, \"{}\")", l, r, name))
+        .join(",\n    ");
 
     format!(
         "
-macro_rules! unicode_scripts {{
-    () => (
-        vec![
-            {}
-        ];
-    )
-}}
+/// Every script with its code points, shared ones from ScriptExtensions.txt included, in
+/// Scripts.txt order.
+pub static SCRIPTS: &[(&str, &[(u32, u32)])] = &[
+    {}
+];
+
+/// `(first, last, name)` of every Scripts.txt line, sorted by code point.
+pub static SCRIPT_RANGES: &[(u32, u32, &str)] = &[
+    {}
+];
 ",
-        codes
+        scripts_rows,
+        ranges_rows
     )
 }
 
@@ -141,7 +280,7 @@ fn write(output: &mut File, codes: String) {
     output.write_all(codes.as_bytes()).unwrap();
 }
 
-fn read_lines(path: &str) -> Vec<String> {
+fn read_lines(path: &Path) -> Vec<String> {
     let f = File::open(path).unwrap();
     let reader = BufReader::new(&f);
     reader
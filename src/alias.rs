@@ -1,16 +1,240 @@
+use error::EditError;
 use family::Family;
 
 use std::cell::RefCell;
 
 
-#[derive(Debug)]
+/// The three family lists of a fontconfig `<alias>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FamilyList {
+    Prefer,
+    Accept,
+    Default,
+}
+
+pub const FAMILY_LISTS: [FamilyList; 3] = [
+    FamilyList::Prefer,
+    FamilyList::Accept,
+    FamilyList::Default,
+];
+
+impl FamilyList {
+    /// The element name, e.g. `<prefer>`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            FamilyList::Prefer => "prefer",
+            FamilyList::Accept => "accept",
+            FamilyList::Default => "default",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<FamilyList> {
+        FAMILY_LISTS.iter().find(|x| x.name() == name).cloned()
+    }
+}
+
+/// How strongly the families of an alias bind to the pattern, `binding="weak"` by default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binding {
+    Weak,
+    Strong,
+    Same,
+}
+
+impl Binding {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Binding::Weak => "weak",
+            Binding::Strong => "strong",
+            Binding::Same => "same",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Binding> {
+        [Binding::Weak, Binding::Strong, Binding::Same]
+            .iter()
+            .find(|x| x.name() == name)
+            .cloned()
+    }
+}
+
+/// A member of a family list: an installed family or another alias of the same config.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry<'a> {
+    Family(&'a RefCell<Family>),
+    Alias(String),
+}
+
+impl<'a> Entry<'a> {
+    pub fn name(&self) -> String {
+        match self {
+            &Entry::Family(family) => family.borrow().name.clone(),
+            &Entry::Alias(ref name) => name.clone(),
+        }
+    }
+
+    pub fn family(&self) -> Option<&'a RefCell<Family>> {
+        match self {
+            &Entry::Family(family) => Some(family),
+            &Entry::Alias(_) => None,
+        }
+    }
+}
+
+/// Families put in front of an alias when the pattern's `lang` contains `language`, e.g. Noto
+/// Sans CJK JP for `ja`. They take precedence over the prefer list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LanguageRule<'a> {
+    pub language: String,
+    pub families: Vec<&'a RefCell<Family>>,
+}
+
+impl<'a> LanguageRule<'a> {
+    pub fn new(language: &str) -> LanguageRule<'a> {
+        LanguageRule {
+            language: String::from(language),
+            families: vec![],
+        }
+    }
+
+    pub fn position(&self, family_name: &str) -> Option<usize> {
+        self.families.iter().position(
+            |x| x.borrow().name == family_name,
+        )
+    }
+
+    /// Whether the rule applies to text in `language`, a normalized tag: the rule for `zh`
+    /// applies to `zh-tw` as well.
+    pub fn applies_to(&self, language: &str) -> bool {
+        language == self.language || language.starts_with(&format!("{}-", self.language))
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct Alias<'a> {
     pub name: String,
-    pub prefer_list: Vec<&'a RefCell<Family>>,
+    pub binding: Binding,
+    pub prefer_list: Vec<Entry<'a>>,
+    pub accept_list: Vec<Entry<'a>>,
+    pub default_list: Vec<Entry<'a>>,
+    pub language_rules: Vec<LanguageRule<'a>>,
 }
 
-/* todo
-impl Alias {
+impl<'a> Alias<'a> {
+    pub fn new(name: &str) -> Alias<'a> {
+        Alias {
+            name: String::from(name),
+            binding: Binding::Weak,
+            prefer_list: vec![],
+            accept_list: vec![],
+            default_list: vec![],
+            language_rules: vec![],
+        }
+    }
+
+    pub fn list(&self, list: FamilyList) -> &Vec<Entry<'a>> {
+        match list {
+            FamilyList::Prefer => &self.prefer_list,
+            FamilyList::Accept => &self.accept_list,
+            FamilyList::Default => &self.default_list,
+        }
+    }
+
+    pub fn list_mut(&mut self, list: FamilyList) -> &mut Vec<Entry<'a>> {
+        match list {
+            FamilyList::Prefer => &mut self.prefer_list,
+            FamilyList::Accept => &mut self.accept_list,
+            FamilyList::Default => &mut self.default_list,
+        }
+    }
+
+    pub fn position(&self, list: FamilyList, family_name: &str) -> Option<usize> {
+        self.list(list).iter().position(|x| x.name() == family_name)
+    }
+
+    /// Inserts `entry` at `position` of `list`, or appends it when `position` is `None`.
+    pub fn insert_entry(
+        &mut self,
+        list: FamilyList,
+        entry: Entry<'a>,
+        position: Option<usize>,
+    ) -> Result<(), EditError> {
+        let entry_name = entry.name();
+        if self.position(list, &entry_name).is_some() {
+            return Err(EditError::DuplicateFamily {
+                alias: self.name.clone(),
+                family: entry_name,
+            });
+        }
+        let len = self.list(list).len();
+        let index = self.checked_position(list, position.unwrap_or(len), 0)?;
+        self.list_mut(list).insert(index, entry);
+        Ok(())
+    }
+
+    pub fn remove_family(
+        &mut self,
+        list: FamilyList,
+        family_name: &str,
+    ) -> Result<Entry<'a>, EditError> {
+        let index = self.checked_family(list, family_name)?;
+        Ok(self.list_mut(list).remove(index))
+    }
+
+    pub fn move_family(
+        &mut self,
+        list: FamilyList,
+        family_name: &str,
+        position: usize,
+    ) -> Result<(), EditError> {
+        let index = self.checked_family(list, family_name)?;
+        let position = self.checked_position(list, position, 1)?;
+        let entry = self.list_mut(list).remove(index);
+        self.list_mut(list).insert(position, entry);
+        Ok(())
+    }
+
+    pub fn language_rule(&self, language: &str) -> Option<&LanguageRule<'a>> {
+        self.language_rules.iter().find(|x| x.language == language)
+    }
+
+    pub fn language_rule_mut(&mut self, language: &str) -> Option<&mut LanguageRule<'a>> {
+        self.language_rules.iter_mut().find(|x| x.language == language)
+    }
+
+    /// Whether any list refers to the alias named `name`.
+    pub fn refers_to(&self, name: &str) -> bool {
+        FAMILY_LISTS.iter().any(|&list| {
+            self.list(list).iter().any(|x| match x {
+                &Entry::Alias(ref alias) => alias == name,
+                _ => false,
+            })
+        })
+    }
+
+    fn checked_family(&self, list: FamilyList, family_name: &str) -> Result<usize, EditError> {
+        self.position(list, family_name).ok_or_else(|| {
+            EditError::FamilyNotInAlias {
+                alias: self.name.clone(),
+                family: String::from(family_name),
+            }
+        })
+    }
 
+    // `occupied` is the number of slots taken by the family being placed
+    fn checked_position(
+        &self,
+        list: FamilyList,
+        position: usize,
+        occupied: usize,
+    ) -> Result<usize, EditError> {
+        if position + occupied <= self.list(list).len() {
+            Ok(position)
+        } else {
+            Err(EditError::PositionOutOfBounds {
+                alias: self.name.clone(),
+                position: position,
+            })
+        }
+    }
 }
-*/
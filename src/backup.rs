@@ -0,0 +1,202 @@
+use deserialization;
+use serialization;
+
+use std::cmp;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+use std::env;
+
+
+/// How many backups are kept of each config, older ones are deleted.
+const BACKUP_LIMIT: usize = 30;
+
+/// A copy of a config as it was before typeholder wrote over it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Backup {
+    pub path: PathBuf,
+    /// Milliseconds since the Unix epoch, also the file name of the backup.
+    pub time: u64,
+}
+
+impl Backup {
+    /// When the backup was taken, like `2017-07-14 02:40:00 UTC`.
+    pub fn label(&self) -> String {
+        format_time(self.time / 1000)
+    }
+}
+
+/// Where the backups of the config at `config_path` are kept, under
+/// `$XDG_DATA_HOME/typeholder/backups`.
+pub fn backup_dir(config_path: &Path) -> io::Result<PathBuf> {
+    Ok(deserialization::user_data_dir()?
+        .join("typeholder/backups")
+        .join(deserialization::path_key(config_path)))
+}
+
+/// Copies the config at `config_path` to a new backup. Nothing is copied when there is no
+/// config yet or it is the same as the latest backup.
+pub fn back_up(config_path: &Path) -> io::Result<Option<Backup>> {
+    back_up_into(&backup_dir(config_path)?, config_path)
+}
+
+/// The backups of the config at `config_path`, latest first.
+pub fn list_backups(config_path: &Path) -> io::Result<Vec<Backup>> {
+    list_in(&backup_dir(config_path)?)
+}
+
+/// Puts `backup` in place of the config at `config_path`. The config is backed up first, so
+/// restoring can be taken back the same way.
+pub fn restore(config_path: &Path, backup: &Backup) -> io::Result<()> {
+    let contents = read(&backup.path)?;
+    back_up(config_path)?;
+    serialization::write_atomically(config_path, &contents)
+}
+
+fn back_up_into(dir: &Path, config_path: &Path) -> io::Result<Option<Backup>> {
+    let contents = match read(config_path) {
+        Ok(contents) => contents,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let backups = list_in(dir)?;
+    if let Some(latest) = backups.first() {
+        if read(&latest.path)? == contents {
+            return Ok(None);
+        }
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() * 1000 + u64::from(x.subsec_nanos() / 1_000_000))
+        .unwrap_or(0);
+    // backups are never overwritten, even when the clock goes back
+    let time = backups.first().map_or(now, |x| cmp::max(now, x.time + 1));
+    let backup = Backup {
+        path: dir.join(format!("{}.conf", time)),
+        time: time,
+    };
+    serialization::write_atomically(&backup.path, &contents)?;
+    for old in backups.iter().skip(BACKUP_LIMIT - 1) {
+        fs::remove_file(&old.path)?;
+    }
+    Ok(Some(backup))
+}
+
+fn list_in(dir: &Path) -> io::Result<Vec<Backup>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+    let mut backups = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        let time = path.file_name()
+            .and_then(|x| x.to_str())
+            .and_then(|x| x.trim_right_matches(".conf").parse::<u64>().ok());
+        if let Some(time) = time {
+            backups.push(Backup {
+                path: path,
+                time: time,
+            });
+        }
+    }
+    backups.sort_by(|a, b| b.time.cmp(&a.time));
+    Ok(backups)
+}
+
+fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let mut contents = vec![];
+    File::open(path)?.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+// a UTC date and time, after Howard Hinnant's `civil_from_days`
+fn format_time(secs: u64) -> String {
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 -
+                           day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // months counted from March, so that leap days come last
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+
+#[test]
+fn test_backups() {
+    assert_eq!(format_time(0), "1970-01-01 00:00:00 UTC");
+    assert_eq!(format_time(951782400), "2000-02-29 00:00:00 UTC");
+    assert_eq!(format_time(1500000000), "2017-07-14 02:40:00 UTC");
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.subsec_nanos())
+        .unwrap_or(0);
+    let dir = env::temp_dir().join(format!("typeholder-backup-{}", nanos));
+    let config_path = dir.join("fonts.conf");
+    let backup_dir = dir.join("backups");
+
+    assert_eq!(back_up_into(&backup_dir, &config_path).unwrap(), None);
+    serialization::write_atomically(&config_path, b"first").unwrap();
+    let first = back_up_into(&backup_dir, &config_path).unwrap().unwrap();
+    // an unchanged config is not copied again
+    assert_eq!(back_up_into(&backup_dir, &config_path).unwrap(), None);
+    serialization::write_atomically(&config_path, b"second").unwrap();
+    let second = back_up_into(&backup_dir, &config_path).unwrap().unwrap();
+    assert!(second.time > first.time);
+    assert_eq!(list_in(&backup_dir).unwrap(), vec![second, first.clone()]);
+    assert_eq!(read(&first.path).unwrap(), b"first");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_path_key() {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.subsec_nanos())
+        .unwrap_or(0);
+    let dir = env::temp_dir().join(format!("typeholder-key-{}", nanos));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    let config_path = dir.join("fonts.conf");
+    // the same file, whether it exists yet or not
+    assert_eq!(
+        deserialization::path_key(&dir.join("./fonts.conf")),
+        deserialization::path_key(&config_path)
+    );
+    File::create(&config_path).unwrap();
+    assert_eq!(
+        deserialization::path_key(&dir.join("sub/../fonts.conf")),
+        deserialization::path_key(&config_path)
+    );
+
+    // a path too long for a file name keeps its end
+    let long_path = |depth| dir.join(format!("{}fonts.conf", "deep/".repeat(depth)));
+    let long_key = deserialization::path_key(&long_path(60));
+    assert!(long_key.len() <= 200 && long_key.ends_with("%deep%fonts.conf"));
+    assert_ne!(long_key, deserialization::path_key(&long_path(61)));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
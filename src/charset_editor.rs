@@ -0,0 +1,399 @@
+use config::Config;
+use family;
+use history::History;
+use itertools::Itertools;
+use range;
+use range::{Range, UNICODE_BLOCKS, UNICODE_SCRIPTS, UNICODE_VERSION};
+use show_error;
+
+use gtk::prelude::*;
+use gtk::{Window, Dialog, DialogFlags, ResponseType, TreeStore, TreeView, TreeViewColumn,
+          TreeModelFilter, TreeIter, CellRendererText, CellRendererToggle,
+          ScrolledWindow, SearchEntry, Orientation, Button, Label};
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::rc::Rc;
+
+// store columns
+const COLUMN_ACTIVE: u32 = 0;
+const COLUMN_LABEL: u32 = 1;
+const COLUMN_KIND: u32 = 2;
+const COLUMN_NAME: u32 = 3;
+const COLUMN_IS_RANGE: u32 = 4;
+
+
+/// Lets the user tick the Unicode blocks, scripts and languages stripped from `family_name`, or
+/// add custom code point ranges. Changes are applied to `config` as they are made, each one
+/// recorded in `history`.
+pub fn run(
+    window: &Window,
+    config: &Rc<RefCell<Config<'static>>>,
+    history: &Rc<RefCell<History>>,
+    family_name: &str,
+) {
+    let dialog = Dialog::new_with_buttons(
+        Some(&format!("Strip Ranges from {}", family_name)),
+        Some(window),
+        DialogFlags::MODAL,
+        &[("_Close", ResponseType::Close.into())],
+    );
+    dialog.set_default_size(420, 560);
+
+    let store = TreeStore::new(
+        &[
+            bool::static_type(),
+            String::static_type(),
+            String::static_type(),
+            String::static_type(),
+            bool::static_type(),
+        ],
+    );
+    let (languages_iter, custom_iter) = fill_store(&store, &config.borrow(), family_name);
+
+    let query = Rc::new(RefCell::new(String::new()));
+    let filter = TreeModelFilter::new(&store, None);
+    {
+        let query = query.clone();
+        filter.set_visible_func(move |model, iter| {
+            let query = query.borrow();
+            let is_range = model.get_value(iter, COLUMN_IS_RANGE as i32).get::<bool>();
+            if query.is_empty() || is_range != Some(true) {
+                return true;
+            }
+            model
+                .get_value(iter, COLUMN_NAME as i32)
+                .get::<String>()
+                .map_or(false, |x| x.to_lowercase().contains(query.as_str()))
+        });
+    }
+
+    let tree = TreeView::new_with_model(&filter);
+    tree.set_headers_visible(false);
+    let column = TreeViewColumn::new();
+    let toggle = CellRendererToggle::new();
+    column.pack_start(&toggle, false);
+    column.add_attribute(&toggle, "active", COLUMN_ACTIVE as i32);
+    column.add_attribute(&toggle, "visible", COLUMN_IS_RANGE as i32);
+    let text = CellRendererText::new();
+    column.pack_start(&text, true);
+    column.add_attribute(&text, "text", COLUMN_LABEL as i32);
+    tree.append_column(&column);
+    {
+        let window = dialog.clone().upcast::<Window>();
+        let store = store.clone();
+        let filter = filter.clone();
+        let config = config.clone();
+        let history = history.clone();
+        let family_name = String::from(family_name);
+        toggle.connect_toggled(move |_, path| {
+            let iter = match filter
+                .convert_path_to_child_path(&path)
+                .and_then(|x| store.get_iter(&x)) {
+                Some(iter) => iter,
+                None => return,
+            };
+            let result = toggle_range(
+                &store,
+                &iter,
+                &mut config.borrow_mut(),
+                &mut history.borrow_mut(),
+                &family_name,
+            );
+            if let Err(err) = result {
+                show_error(&window, &err.to_string());
+            }
+        });
+    }
+
+    let search_entry = SearchEntry::new();
+    search_entry.set_placeholder_text(Some(&format!("Search Unicode {} ranges", UNICODE_VERSION)));
+    {
+        let tree = tree.clone();
+        let filter = filter.clone();
+        search_entry.connect_search_changed(move |entry| {
+            *query.borrow_mut() = entry.get_text().unwrap_or_default().trim().to_lowercase();
+            filter.refilter();
+            tree.expand_all();
+        });
+    }
+
+    let name_entry = ::gtk::Entry::new();
+    name_entry.set_placeholder_text(Some("Name"));
+    let start_entry = ::gtk::Entry::new();
+    start_entry.set_placeholder_text(Some("First, e.g. U+4E00"));
+    start_entry.set_width_chars(10);
+    let end_entry = ::gtk::Entry::new();
+    end_entry.set_placeholder_text(Some("Last, e.g. U+9FFF"));
+    end_entry.set_width_chars(10);
+    let add_button = Button::new_with_label("Add");
+    {
+        let window = dialog.clone().upcast::<Window>();
+        let tree = tree.clone();
+        let store = store.clone();
+        let config = config.clone();
+        let history = history.clone();
+        let family_name = String::from(family_name);
+        let name_entry = name_entry.clone();
+        let start_entry = start_entry.clone();
+        let end_entry = end_entry.clone();
+        add_button.connect_clicked(move |_| {
+            let range = custom_range(
+                &name_entry.get_text().unwrap_or_default(),
+                &start_entry.get_text().unwrap_or_default(),
+                &end_entry.get_text().unwrap_or_default(),
+            );
+            let result = range.and_then(|x| {
+                let description = format!("Strip {} from {}", x.name(), family_name);
+                history.borrow_mut().apply(
+                    &mut config.borrow_mut(),
+                    &description,
+                    |y| y.strip_range(&family_name, x.clone()),
+                )?;
+                Ok(x)
+            });
+            match result {
+                Ok(range) => {
+                    insert_range_row(&store, &custom_iter, &range, true, None);
+                    tree.expand_all();
+                    name_entry.set_text("");
+                    start_entry.set_text("");
+                    end_entry.set_text("");
+                }
+                Err(err) => show_error(&window, &err.to_string()),
+            }
+        });
+    }
+
+    let language_entry = ::gtk::Entry::new();
+    language_entry.set_placeholder_text(Some("Language, e.g. ja or zh-tw"));
+    let language_button = Button::new_with_label("Strip Language");
+    {
+        let window = dialog.clone().upcast::<Window>();
+        let tree = tree.clone();
+        let store = store.clone();
+        let config = config.clone();
+        let history = history.clone();
+        let family_name = String::from(family_name);
+        let language_entry = language_entry.clone();
+        language_button.connect_clicked(move |_| {
+            let text = language_entry.get_text().unwrap_or_default();
+            let language = family::normalize_language(&text).unwrap_or(text);
+            let description = format!("Strip {} from {}", language, family_name);
+            let result = history.borrow_mut().apply(
+                &mut config.borrow_mut(),
+                &description,
+                |x| x.strip_language(&family_name, &language),
+            );
+            if let Err(err) = result {
+                show_error(&window, &err.to_string());
+                return;
+            }
+            match find_row(&store, &languages_iter, &language) {
+                Some(iter) => store.set_value(&iter, COLUMN_ACTIVE, &true.to_value()),
+                None => insert_language_row(&store, &languages_iter, &language, true),
+            }
+            tree.expand_all();
+            language_entry.set_text("");
+        });
+    }
+
+    let language_box = ::gtk::Box::new(Orientation::Horizontal, 6);
+    language_box.pack_start(&language_entry, true, true, 0);
+    language_box.pack_start(&language_button, false, false, 0);
+
+    let custom_box = ::gtk::Box::new(Orientation::Horizontal, 6);
+    custom_box.pack_start(&name_entry, true, true, 0);
+    custom_box.pack_start(&start_entry, false, false, 0);
+    custom_box.pack_start(&Label::new(Some("–")), false, false, 0);
+    custom_box.pack_start(&end_entry, false, false, 0);
+    custom_box.pack_start(&add_button, false, false, 0);
+
+    let scrolled = ScrolledWindow::new(None, None);
+    scrolled.set_vexpand(true);
+    scrolled.add(&tree);
+
+    let content = dialog.get_content_area();
+    content.set_spacing(6);
+    content.add(&search_entry);
+    content.add(&scrolled);
+    content.add(&language_box);
+    content.add(&custom_box);
+
+    tree.expand_all();
+    dialog.show_all();
+    dialog.run();
+    dialog.destroy();
+}
+
+/// Fills the Blocks, Scripts, Languages and Custom groups, ticking what `family_name` strips.
+/// Returns the Languages and Custom group rows.
+fn fill_store(store: &TreeStore, config: &Config, family_name: &str) -> (TreeIter, TreeIter) {
+    let cell = match config.family(family_name) {
+        Ok(family) => family,
+        Err(_) => {
+            return (insert_group_row(store, "Languages"), insert_group_row(store, "Custom"))
+        }
+    };
+    let family = cell.borrow();
+    let stripped = config.scan_match(cell).map_or(&[][..], |x| &x.stripped_ranges[..]);
+    let stripped_languages = config.stripped_languages(cell);
+    // without charset data every percentage would be 0
+    let coverage = |range: &Range| if family.charset.is_empty() {
+        None
+    } else {
+        Some(family.coverage_ratio(range))
+    };
+    let is_stripped = |range: &Range| {
+        stripped.iter().any(|x| {
+            x.kind() == range.kind() && x.name() == range.name()
+        })
+    };
+
+    let groups = [("Blocks", &*UNICODE_BLOCKS), ("Scripts", &*UNICODE_SCRIPTS)];
+    for &(label, ranges) in groups.iter() {
+        let group_iter = insert_group_row(store, label);
+        for range in ranges {
+            insert_range_row(store, &group_iter, range, is_stripped(range), coverage(range));
+        }
+    }
+
+    // the languages fontconfig reports for the family, and any stripped ones it doesn't
+    let languages_iter = insert_group_row(store, "Languages");
+    let languages = family
+        .languages
+        .iter()
+        .chain(stripped_languages)
+        .sorted()
+        .into_iter()
+        .dedup();
+    for language in languages {
+        let active = stripped_languages.contains(language);
+        insert_language_row(store, &languages_iter, language, active);
+    }
+
+    let custom_iter = insert_group_row(store, "Custom");
+    for range in stripped.iter().filter(|x| x.kind() == "Custom") {
+        insert_range_row(store, &custom_iter, range, true, coverage(range));
+    }
+    (languages_iter, custom_iter)
+}
+
+fn insert_group_row(store: &TreeStore, label: &str) -> TreeIter {
+    store.insert_with_values(
+        None,
+        None,
+        &[COLUMN_ACTIVE, COLUMN_LABEL, COLUMN_KIND, COLUMN_NAME, COLUMN_IS_RANGE],
+        &[&false, &label, &"", &"", &false],
+    )
+}
+
+fn insert_range_row(
+    store: &TreeStore,
+    group_iter: &TreeIter,
+    range: &Range,
+    active: bool,
+    coverage: Option<f64>,
+) {
+    let mut label = match range {
+        &Range::Script { .. } => String::from(range.name()),
+        _ => {
+            let (first, last) = range.code_points()[0];
+            format!("{} (U+{:04X}..U+{:04X})", range.name(), first, last)
+        }
+    };
+    if let Some(ratio) = coverage {
+        label.push_str(&format!(" {:.0}%", ratio * 100.0));
+    }
+    store.insert_with_values(
+        Some(group_iter),
+        None,
+        &[COLUMN_ACTIVE, COLUMN_LABEL, COLUMN_KIND, COLUMN_NAME, COLUMN_IS_RANGE],
+        &[&active, &label, &range.kind(), &range.name(), &true],
+    );
+}
+
+fn insert_language_row(store: &TreeStore, group_iter: &TreeIter, language: &str, active: bool) {
+    store.insert_with_values(
+        Some(group_iter),
+        None,
+        &[COLUMN_ACTIVE, COLUMN_LABEL, COLUMN_KIND, COLUMN_NAME, COLUMN_IS_RANGE],
+        &[&active, &language, &"Language", &language, &true],
+    );
+}
+
+/// The row under `group_iter` named `name`.
+fn find_row(store: &TreeStore, group_iter: &TreeIter, name: &str) -> Option<TreeIter> {
+    let iter = store.iter_children(Some(group_iter))?;
+    loop {
+        let row_name = store.get_value(&iter, COLUMN_NAME as i32).get::<String>();
+        if row_name.map_or(false, |x| x == name) {
+            return Some(iter);
+        }
+        if !store.iter_next(&iter) {
+            return None;
+        }
+    }
+}
+
+/// Strips or unstrips the range of a row. Unticked custom ranges are gone for good, so their
+/// rows are removed.
+fn toggle_range(
+    store: &TreeStore,
+    iter: &TreeIter,
+    config: &mut Config,
+    history: &mut History,
+    family_name: &str,
+) -> Result<(), Box<Error>> {
+    let value = |column: u32| store.get_value(iter, column as i32);
+    let active = value(COLUMN_ACTIVE).get::<bool>().unwrap_or(false);
+    let kind = value(COLUMN_KIND).get::<String>().unwrap_or_default();
+    let name = value(COLUMN_NAME).get::<String>().unwrap_or_default();
+
+    let description = format!(
+        "{} {} from {}",
+        if active { "Unstrip" } else { "Strip" },
+        name,
+        family_name
+    );
+    if kind == "Language" {
+        if active {
+            history.apply(config, &description, |x| {
+                x.unstrip_language(family_name, &name)
+            })?;
+        } else {
+            history.apply(config, &description, |x| {
+                x.strip_language(family_name, &name)
+            })?;
+        }
+        store.set_value(iter, COLUMN_ACTIVE, &(!active).to_value());
+    } else if active {
+        history.apply(config, &description, |x| {
+            x.unstrip_range(family_name, &name, &kind)
+        })?;
+        if kind == "Custom" {
+            store.remove(iter);
+        } else {
+            store.set_value(iter, COLUMN_ACTIVE, &false.to_value());
+        }
+    } else {
+        let range = if kind == "Block" {
+            Range::block(&name)
+        } else {
+            Range::script(&name)
+        };
+        let range = range.ok_or_else(|| format!("Unknown Unicode range \"{}\"", name))?;
+        history.apply(config, &description, |x| x.strip_range(family_name, range))?;
+        store.set_value(iter, COLUMN_ACTIVE, &true.to_value());
+    }
+    Ok(())
+}
+
+fn custom_range(name: &str, start: &str, end: &str) -> Result<Range, Box<Error>> {
+    let (first, last) = (range::checked_code_point(start)?, range::checked_code_point(end)?);
+    let name = match name.trim() {
+        "" => format!("U+{:04X}..U+{:04X}", first, last),
+        name => String::from(name),
+    };
+    Ok(Range::custom(&name, first, last)?)
+}
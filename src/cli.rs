@@ -0,0 +1,586 @@
+use alias::{Alias, Binding, FamilyList, FAMILY_LISTS};
+use backup;
+use config::Config;
+use deserialization;
+use effective;
+use effective::EffectiveConfig;
+use error::EditError;
+use family;
+use fontconfig;
+use history;
+use history::History;
+use itertools::Itertools;
+use range;
+use range::Range;
+use search::FontQuery;
+use serialization;
+use simulation;
+use validation;
+use validation::Severity;
+
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+
+
+pub const USAGE: &str = "Usage:
+    typeholder [--config <path> | --conf-d] [--] [<command>]
+
+Options:
+    --config <path>     edit <path> instead of $XDG_CONFIG_HOME/fontconfig/fonts.conf
+    --conf-d            edit typeholder's own fontconfig/conf.d/50-typeholder.conf
+    --                  end the options, the command follows
+
+Commands:
+    typeholder                                    start the GTK window
+    typeholder show                               print the generated fonts.conf
+    typeholder families [<query>]                 list installed families, filtered by name
+                                                  and script:<name>, style:<name>, mono
+    typeholder effective                          show the merged system and user configuration
+    typeholder alias list
+    typeholder foreign                            list hand-written aliases and scan matches
+    typeholder adopt (alias | match) <index>      let typeholder manage a hand-written entry
+    typeholder alias add <alias>
+    typeholder alias rename <alias> <new name>
+    typeholder alias delete <alias>
+    typeholder alias (prefer | accept | default) <alias> <family or alias> [--position <n>]
+    typeholder alias remove <alias> <family> [--from (prefer | accept | default)]
+    typeholder alias move <alias> <family> <position> [--in (prefer | accept | default)]
+    typeholder alias binding <alias> (weak | strong | same)
+    typeholder alias lang <alias> <language> <family> [--position <n>]
+    typeholder alias unlang <alias> <language> [<family>]
+    typeholder strip <family> (--block <name> | --script <name> | --custom <name> <start> <end>)
+    typeholder unstrip <family> (--block | --script | --custom) <name>
+    typeholder strip <family> --lang <language>   stop using a family for a language, e.g. ja
+    typeholder unstrip <family> --lang <language>
+    typeholder covering <code point>              list families covering a code point
+    typeholder coverage <family> (--block | --script) <name>
+    typeholder simulate <alias> <sample> [--lang <language>]
+                                                  show which family draws each character
+    typeholder compare <alias> <sample> [--lang <language>]
+                                                  list characters fontconfig draws differently
+    typeholder undo                               revert the last edit made from the command line
+    typeholder redo                               apply the last undone edit again
+    typeholder check                              report what fonts.dtd or typeholder would reject,
+                                                  exiting with 1 on errors
+    typeholder restore [<index>]                  list the backups kept before each save, or
+                                                  put one back in place of the config
+
+Code points are written as U+4E00, 0x4e00 or decimal 19968.
+";
+
+/// Splits the global options off `args`, returning the config path and the command. Options
+/// end at the command or at `--`, so that its arguments may look like options themselves.
+/// Malformed options are a usage error, see `report`.
+pub fn parse_options(args: &[String]) -> Result<(PathBuf, Vec<String>), Box<Error>> {
+    let mut config_path = None;
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.peek().cloned() {
+        match arg.as_str() {
+            "--config" if config_path.is_none() => {
+                args.next();
+                let path = args.next().ok_or_else(|| UsageError(String::from("--config")))?;
+                config_path = Some(PathBuf::from(path));
+            }
+            "--conf-d" if config_path.is_none() => {
+                args.next();
+                config_path = Some(deserialization::drop_in_config_path()?);
+            }
+            "--config" | "--conf-d" => return Err(Box::new(UsageError(arg.clone()))),
+            "--" => {
+                args.next();
+                break;
+            }
+            _ => break,
+        }
+    }
+    let command = args.cloned().collect_vec();
+    let config_path = match config_path {
+        Some(path) => path,
+        None => deserialization::user_config_path()?,
+    };
+    Ok((config_path, command))
+}
+
+/// Runs a headless command and returns the process exit status.
+pub fn run(config_path: &Path, args: &[String]) -> i32 {
+    // restoring and checking have to work when the config no longer parses
+    let result = match args.first().map(String::as_str) {
+        Some("restore") => run_restore(config_path, &args[1..]),
+        Some("check") if args.len() == 1 => run_check(config_path),
+        _ => {
+            deserialization::list_installed_families()
+                .map_err(Box::<Error>::from)
+                .and_then(|families| {
+                    let text = deserialization::read_file(config_path)?;
+                    let journal_path = history::journal_path(config_path)?;
+                    let history = history::read_journal(&journal_path)?;
+                    // undoing may bring back families only the journal still names
+                    let placeholders = {
+                        let mut texts = history.texts();
+                        texts.push(&text);
+                        deserialization::placeholders(&texts, &families)
+                    };
+                    let mut config = deserialization::parse(&text, &families, &placeholders)?;
+                    run_journaled(&mut config, config_path, history, args)
+                })
+        }
+    };
+    match result {
+        Ok(()) => 0,
+        Err(err) => report(err),
+    }
+}
+
+/// Prints `err` and returns the exit status for it, 2 with the usage for usage errors.
+pub fn report(err: Box<Error>) -> i32 {
+    eprintln!("typeholder: {}", err);
+    if err.downcast_ref::<UsageError>().is_some() {
+        eprint!("{}", USAGE);
+        2
+    } else {
+        1
+    }
+}
+
+// Prints the problems `validation` finds in the config at `path`, failing when any of them is
+// an error.
+fn run_check(path: &Path) -> Result<(), Box<Error>> {
+    let text = deserialization::read_file(path)?;
+    if !path.exists() {
+        println!("{} does not exist yet, checking the defaults", path.display());
+    }
+    let families = deserialization::list_installed_families()?;
+    let diagnostics = validation::validate(&text, &families);
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+    match diagnostics.iter().filter(|x| x.severity == Severity::Error).count() {
+        0 => Ok(()),
+        1 => Err(From::from(format!("{} has an error", path.display()))),
+        errors => Err(From::from(format!("{} has {} errors", path.display(), errors))),
+    }
+}
+
+// Lists the backups of the config at `path`, or puts the one at `args[0]` back.
+fn run_restore(path: &Path, args: &[String]) -> Result<(), Box<Error>> {
+    let backups = backup::list_backups(path)?;
+    match &args.iter().map(String::as_str).collect_vec()[..] {
+        &[] if backups.is_empty() => println!("No backups of {}", path.display()),
+        &[] => {
+            for (i, backup) in backups.iter().enumerate() {
+                println!("#{}: {}", i, backup.label());
+            }
+        }
+        &[index] => {
+            let backup = backups.get(parse_position(index)?).ok_or_else(|| {
+                format!("No backup #{} of {}", index, path.display())
+            })?;
+            backup::restore(path, backup)?;
+            println!("Restored {} as of {}", path.display(), backup.label());
+        }
+        _ => return Err(Box::new(UsageError(format!("restore {}", args.join(" "))))),
+    }
+    Ok(())
+}
+
+// Runs `undo` and `redo` against `history`, the journal of the config at `path`, and records
+// every other command that changed the config in it.
+fn run_journaled(
+    config: &mut Config,
+    path: &Path,
+    mut history: History,
+    args: &[String],
+) -> Result<(), Box<Error>> {
+    let journal_path = history::journal_path(path)?;
+    let current = serialization::to_string(config)?;
+    let undo = match &args.iter().map(String::as_str).collect_vec()[..] {
+        &["undo"] => true,
+        &["redo"] => false,
+        _ => {
+            run_command(config, path, args)?;
+            let after = serialization::to_string(config)?;
+            if after != current {
+                history.record(&args.join(" "), current, after);
+                history::write_journal(&journal_path, &history)?;
+            }
+            return Ok(());
+        }
+    };
+    let (expected, target, description) = {
+        let edit = if undo {
+            history.undo_edit().map(|x| (&x.after, &x.before, &x.description))
+        } else {
+            history.redo_edit().map(|x| (&x.before, &x.after, &x.description))
+        };
+        let (expected, target, description) =
+            edit.ok_or_else(|| format!("Nothing to {}", args[0]))?;
+        (expected.clone(), target.clone(), description.clone())
+    };
+    if current != expected {
+        return Err(From::from(format!(
+            "{} was changed outside typeholder since the last edit, cannot {}",
+            path.display(),
+            args[0]
+        )));
+    }
+    *config = deserialization::parse(&target, config.families, config.placeholders.clone())?;
+    serialization::save(config, path)?;
+    history::write_journal(&journal_path, &history)?;
+    println!("{} {}", if undo { "Undid" } else { "Redid" }, description);
+    Ok(())
+}
+
+fn run_command(config: &mut Config, path: &Path, args: &[String]) -> Result<(), Box<Error>> {
+    let args = args.iter().map(String::as_str).collect_vec();
+    match &args[..] {
+        &["show"] => print!("{}", serialization::to_string(config)?),
+        &["families", ref query..] => {
+            let query = FontQuery::parse(&query.join(" "))?;
+            if query.needs_font_info() {
+                deserialization::load_font_info(config.families)?;
+            }
+            for family in config.families {
+                if query.matches(&family.borrow()) {
+                    println!("{}", family.borrow().name);
+                }
+            }
+        }
+        &["effective"] => print_effective(&effective::resolve_system()),
+        &["alias", "list"] => {
+            for alias in &config.aliases {
+                print_alias(alias, &alias.name);
+            }
+        }
+        &["foreign"] => {
+            for (i, alias) in config.foreign_aliases().iter().enumerate() {
+                print_alias(alias, &format!("alias #{}: {}", i, alias.name));
+            }
+            for (i, scan_match) in config.foreign_scan_matches().iter().enumerate() {
+                println!("match #{}: {}", i, scan_match.family.borrow().name);
+                for range in &scan_match.ranges {
+                    println!("    {}", range.name());
+                }
+            }
+        }
+        &["adopt", "alias", index] => {
+            config.adopt_alias(parse_position(index)?)?;
+            serialization::save(config, path)?;
+        }
+        &["adopt", "match", index] => {
+            config.adopt_scan_match(parse_position(index)?)?;
+            serialization::save(config, path)?;
+        }
+        &["alias", "add", alias] => {
+            config.add_alias(alias)?;
+            serialization::save(config, path)?;
+        }
+        &["alias", "rename", alias, new_name] => {
+            config.rename_alias(alias, new_name)?;
+            serialization::save(config, path)?;
+        }
+        &["alias", "delete", alias] => {
+            config.remove_alias(alias)?;
+            serialization::save(config, path)?;
+        }
+        &["alias", "remove", alias, family] => {
+            config.remove_family(alias, FamilyList::Prefer, family)?;
+            serialization::save(config, path)?;
+        }
+        &["alias", "remove", alias, family, "--from", list] => {
+            config.remove_family(alias, parse_list(list)?, family)?;
+            serialization::save(config, path)?;
+        }
+        &["alias", "move", alias, family, position] => {
+            config.move_family(alias, FamilyList::Prefer, family, parse_position(position)?)?;
+            serialization::save(config, path)?;
+        }
+        &["alias", "move", alias, family, position, "--in", list] => {
+            let position = parse_position(position)?;
+            config.move_family(alias, parse_list(list)?, family, position)?;
+            serialization::save(config, path)?;
+        }
+        &["alias", "binding", alias, binding] => {
+            config.alias_mut(alias)?.binding = Binding::from_name(binding).ok_or_else(|| {
+                UsageError(String::from(binding))
+            })?;
+            serialization::save(config, path)?;
+        }
+        &["alias", "lang", alias, language, family] => {
+            add_language_family(config, alias, language, family, None)?;
+            serialization::save(config, path)?;
+        }
+        &["alias", "lang", alias, language, family, "--position", position] => {
+            let position = parse_position(position)?;
+            add_language_family(config, alias, language, family, Some(position))?;
+            serialization::save(config, path)?;
+        }
+        &["alias", "unlang", alias, language] => {
+            config.remove_language_rule(alias, language)?;
+            serialization::save(config, path)?;
+        }
+        &["alias", "unlang", alias, language, family] => {
+            config.remove_language_family(alias, language, family)?;
+            serialization::save(config, path)?;
+        }
+        // after the other `alias` subcommands, which would otherwise parse as list names
+        &["alias", list, alias, family] => {
+            config.insert_family(alias, parse_list(list)?, family, None)?;
+            serialization::save(config, path)?;
+        }
+        &["alias", list, alias, family, "--position", position] => {
+            let position = parse_position(position)?;
+            config.insert_family(alias, parse_list(list)?, family, Some(position))?;
+            serialization::save(config, path)?;
+        }
+        &["covering", code_point] => {
+            deserialization::load_font_info(config.families)?;
+            let code_point = range::checked_code_point(code_point)?;
+            for family in family::families_covering(config.families, code_point) {
+                println!("{}", family.borrow().name);
+            }
+        }
+        &["coverage", family, kind, name] => {
+            deserialization::load_font_info(config.families)?;
+            let ratio = config.family(family)?.borrow().coverage_ratio(
+                &named_range(kind, name)?,
+            );
+            println!("{:.1}%", ratio * 100.0);
+        }
+        &["simulate", alias, sample] => print_simulation(config, alias, sample, None)?,
+        &["simulate", alias, sample, "--lang", language] => {
+            print_simulation(config, alias, sample, Some(language))?
+        }
+        &["compare", alias, sample] => print_comparison(config, path, alias, sample, None)?,
+        &["compare", alias, sample, "--lang", language] => {
+            print_comparison(config, path, alias, sample, Some(language))?
+        }
+        &["strip", family, "--custom", name, start, end] => {
+            let range = Range::custom(
+                name,
+                range::checked_code_point(start)?,
+                range::checked_code_point(end)?,
+            )?;
+            config.strip_range(family, range)?;
+            serialization::save(config, path)?;
+            warn_without_fallback(config)?;
+            warn_redundant(config, family);
+        }
+        &["strip", family, "--lang", language] => {
+            config.strip_language(family, language)?;
+            serialization::save(config, path)?;
+        }
+        &["strip", family, kind, name] => {
+            config.strip_range(family, named_range(kind, name)?)?;
+            serialization::save(config, path)?;
+            warn_without_fallback(config)?;
+            warn_redundant(config, family);
+        }
+        &["unstrip", family, "--lang", language] => {
+            config.unstrip_language(family, language)?;
+            serialization::save(config, path)?;
+        }
+        &["unstrip", family, kind, name] => {
+            config.unstrip_range(family, name, range_kind(kind)?)?;
+            serialization::save(config, path)?;
+        }
+        _ => return Err(Box::new(UsageError(args.join(" ")))),
+    }
+    Ok(())
+}
+
+fn warn_without_fallback(config: &Config) -> Result<(), Box<Error>> {
+    deserialization::load_font_info(config.families)?;
+    for (family, range) in config.strips_without_fallback() {
+        // a custom range is named by the user, the block it starts in tells what it holds
+        let kind = match range {
+            Range::Custom { code_points: (first, _), .. } => {
+                match range::block_of(first) {
+                    Some(block) => format!("{} in {}", range.kind(), block),
+                    None => String::from(range.kind()),
+                }
+            }
+            _ => String::from(range.kind()),
+        };
+        eprintln!(
+            "typeholder: warning: no other family covers {} ({}) stripped from {}",
+            range.name(),
+            kind,
+            family.borrow().name
+        );
+    }
+    Ok(())
+}
+
+fn warn_redundant(config: &Config, family: &str) {
+    if let Some(scan_match) = config.family(family).ok().and_then(|x| config.scan_match(x)) {
+        for range in scan_match.redundant_ranges() {
+            eprintln!(
+                "typeholder: warning: other ranges stripped from {} already cover {} ({})",
+                scan_match.family.borrow().name,
+                range.name(),
+                range.kind()
+            );
+        }
+    }
+}
+
+/// Puts `family` into the `language` rule of `alias`, adding the rule if needed.
+fn add_language_family(
+    config: &mut Config,
+    alias: &str,
+    language: &str,
+    family: &str,
+    position: Option<usize>,
+) -> Result<(), EditError> {
+    let normalized = family::normalize_language(language).unwrap_or_default();
+    if config.alias(alias)?.language_rule(&normalized).is_none() {
+        config.add_language_rule(alias, language)?;
+    }
+    config.insert_language_family(alias, language, family, position)
+}
+
+fn print_alias(alias: &Alias, label: &str) {
+    match alias.binding {
+        Binding::Weak => println!("{}", label),
+        binding => println!("{} ({} binding)", label, binding.name()),
+    }
+    for rule in &alias.language_rules {
+        println!("  lang {}", rule.language);
+        for (i, family) in rule.families.iter().enumerate() {
+            println!("    {}. {}", i, family.borrow().label());
+        }
+    }
+    for &list in FAMILY_LISTS.iter() {
+        if alias.list(list).is_empty() {
+            continue;
+        }
+        println!("  {}", list.name());
+        for (i, entry) in alias.list(list).iter().enumerate() {
+            match entry.family() {
+                Some(family) => println!("    {}. {}", i, family.borrow().label()),
+                None => println!("    {}. {}", i, entry.name()),
+            }
+        }
+    }
+}
+
+fn print_simulation(
+    config: &Config,
+    alias: &str,
+    sample: &str,
+    language: Option<&str>,
+) -> Result<(), Box<Error>> {
+    deserialization::load_font_info(config.families)?;
+    for resolved in simulation::simulate(config, alias, sample, language)? {
+        let family = match resolved.family {
+            Some(family) => family.borrow().name.clone(),
+            None => String::from("(none)"),
+        };
+        println!(
+            "U+{:04X} {} {} ({})",
+            resolved.character as u32,
+            resolved.character,
+            family,
+            range::code_point_context(resolved.character as u32)
+        );
+    }
+    Ok(())
+}
+
+fn print_comparison(
+    config: &Config,
+    path: &Path,
+    alias: &str,
+    sample: &str,
+    language: Option<&str>,
+) -> Result<(), Box<Error>> {
+    deserialization::load_font_info(config.families)?;
+    for difference in fontconfig::compare(config, path, alias, sample, language)? {
+        println!(
+            "U+{:04X} {} simulated {}, fontconfig {}",
+            difference.character as u32,
+            difference.character,
+            difference.simulated.map_or(
+                String::from("(none)"),
+                |x| x.borrow().name.clone(),
+            ),
+            difference.actual.unwrap_or_else(|| String::from("(none)"))
+        );
+    }
+    Ok(())
+}
+
+fn print_effective(effective: &EffectiveConfig) {
+    for alias in &effective.aliases {
+        println!("{}", alias.name);
+        for &list in FAMILY_LISTS.iter() {
+            if alias.list(list).is_empty() {
+                continue;
+            }
+            println!("  {}", list.name());
+            for (i, family) in alias.list(list).iter().enumerate() {
+                println!(
+                    "    {}. {}  ({})",
+                    i,
+                    effective::family_label(&family.value),
+                    family.source.display()
+                );
+            }
+        }
+    }
+    for family in &effective.scan_matches {
+        println!("scan match {}  ({})", family.value, family.source.display());
+    }
+    for problem in &effective.problems {
+        eprintln!("{}: {}", problem.source.display(), problem.value);
+    }
+}
+
+fn range_kind(flag: &str) -> Result<&'static str, Box<Error>> {
+    match flag {
+        "--block" => Ok("Block"),
+        "--script" => Ok("Script"),
+        "--custom" => Ok("Custom"),
+        _ => Err(Box::new(UsageError(String::from(flag)))),
+    }
+}
+
+fn named_range(flag: &str, name: &str) -> Result<Range, Box<Error>> {
+    let range = match range_kind(flag)? {
+        "Block" => Range::block(name),
+        "Script" => Range::script(name),
+        _ => return Err(Box::new(UsageError(String::from(flag)))),
+    };
+    range.ok_or_else(|| From::from(format!("Unknown Unicode range \"{}\"", name)))
+}
+
+/// The `prefer`, `accept` or `default` list argument.
+fn parse_list(text: &str) -> Result<FamilyList, Box<Error>> {
+    FamilyList::from_name(text).ok_or_else(|| {
+        From::from(UsageError(String::from(text)))
+    })
+}
+
+fn parse_position(text: &str) -> Result<usize, Box<Error>> {
+    text.parse().map_err(|_| {
+        From::from(format!("\"{}\" is not a valid position", text))
+    })
+}
+
+
+#[derive(Debug)]
+struct UsageError(String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid command \"{}\"", self.0)
+    }
+}
+
+impl Error for UsageError {
+    fn description(&self) -> &str {
+        "Invalid command"
+    }
+}
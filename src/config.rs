@@ -1,27 +1,757 @@
-use alias::Alias;
-use itertools::Itertools;
+use alias::{Alias, Entry, FamilyList, LanguageRule, FAMILY_LISTS};
+use error::EditError;
+use family;
 use family::Family;
-use range::Range;
+use foreign;
+use foreign::{ForeignScanMatch, Names};
+use range::{CodePointSet, Range};
+use scan_match::ScanMatch;
+use serialization;
 use sxd_document::Package;
 
 use std::cell::RefCell;
 
+#[cfg(test)]
+use consts::DEFAULT_FONTS_CONF;
+#[cfg(test)]
+use deserialization;
+
 
 #[derive(Debug)]
 pub struct Config<'a> {
-    pub scan_matches: Vec<&'a RefCell<Family>>,
+    pub families: &'a Vec<RefCell<Family>>,
+    /// The families the config names that are not installed, see
+    /// `deserialization::placeholders`.
+    pub placeholders: Vec<&'a RefCell<Family>>,
+    pub scan_matches: Vec<ScanMatch<'a>>,
     pub aliases: Vec<Alias<'a>>,
     pub residue: Package,
 }
 
-/* todo
-impl Config<'a> {
-    fn add_family(&self) {
+impl<'a> PartialEq for Config<'a> {
+    fn eq(&self, other: &Config<'a>) -> bool {
+        self.scan_matches == other.scan_matches && self.aliases == other.aliases &&
+            serialization::format_package(&self.residue) ==
+                serialization::format_package(&other.residue)
+    }
+}
+
+impl<'a> Config<'a> {
+    /// An installed family, or an uninstalled one the config still strips ranges from.
+    pub fn family(&self, name: &str) -> Result<&'a RefCell<Family>, EditError> {
+        self.families
+            .iter()
+            .chain(self.scan_matches.iter().map(|x| x.family))
+            .find(|x| x.borrow().name == name)
+            .ok_or_else(|| EditError::UnknownFamily(String::from(name)))
+    }
+
+    pub fn alias(&self, name: &str) -> Result<&Alias<'a>, EditError> {
+        self.aliases.iter().find(|x| x.name == name).ok_or_else(|| {
+            EditError::UnknownAlias(String::from(name))
+        })
+    }
+
+    pub fn alias_mut(&mut self, name: &str) -> Result<&mut Alias<'a>, EditError> {
+        self.aliases.iter_mut().find(|x| x.name == name).ok_or_else(
+            || {
+                EditError::UnknownAlias(String::from(name))
+            },
+        )
+    }
+
+    pub fn add_alias(&mut self, name: &str) -> Result<(), EditError> {
+        self.check_new_alias_name(name)?;
+        self.aliases.push(Alias::new(name));
+        Ok(())
+    }
+
+    /// Removes an alias along with every reference other aliases make to it.
+    pub fn remove_alias(&mut self, name: &str) -> Result<Alias<'a>, EditError> {
+        let index = self.aliases.iter().position(|x| x.name == name).ok_or_else(|| {
+            EditError::UnknownAlias(String::from(name))
+        })?;
+        let removed = self.aliases.remove(index);
+        for alias in &mut self.aliases {
+            for &list in FAMILY_LISTS.iter() {
+                alias.list_mut(list).retain(|x| *x != Entry::Alias(String::from(name)));
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Renames an alias, updating every reference other aliases make to it.
+    pub fn rename_alias(&mut self, name: &str, new_name: &str) -> Result<(), EditError> {
+        self.check_new_alias_name(new_name)?;
+        self.alias_mut(name)?.name = String::from(new_name);
+        for alias in &mut self.aliases {
+            for &list in FAMILY_LISTS.iter() {
+                for entry in alias.list_mut(list).iter_mut() {
+                    if *entry == Entry::Alias(String::from(name)) {
+                        *entry = Entry::Alias(String::from(new_name));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // a name that is empty or taken by a family could never be put into a list, see `entry`
+    fn check_new_alias_name(&self, name: &str) -> Result<(), EditError> {
+        if name.trim().is_empty() {
+            return Err(EditError::EmptyAliasName);
+        }
+        if self.family(name).is_ok() {
+            return Err(EditError::AliasNamesFamily(String::from(name)));
+        }
+        if self.alias(name).is_ok() {
+            return Err(EditError::DuplicateAlias(String::from(name)));
+        }
+        Ok(())
+    }
+
+    /// Resolves a list member by name, preferring an installed family over an alias.
+    pub fn entry(&self, name: &str) -> Result<Entry<'a>, EditError> {
+        match self.family(name) {
+            Ok(family) => Ok(Entry::Family(family)),
+            Err(err) => {
+                if self.alias(name).is_ok() {
+                    Ok(Entry::Alias(String::from(name)))
+                } else {
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    /// Whether `alias` can be reached from `from` by following alias entries.
+    pub fn reaches(&self, from: &str, alias: &str) -> bool {
+        let mut pending = vec![String::from(from)];
+        let mut visited: Vec<String> = vec![];
+        while let Some(name) = pending.pop() {
+            if name == alias {
+                return true;
+            }
+            if visited.contains(&name) {
+                continue;
+            }
+            if let Ok(current) = self.alias(&name) {
+                for &list in FAMILY_LISTS.iter() {
+                    for entry in current.list(list) {
+                        if let &Entry::Alias(ref next) = entry {
+                            pending.push(next.clone());
+                        }
+                    }
+                }
+            }
+            visited.push(name);
+        }
+        false
+    }
+
+    /// Inserts an installed family or another alias into `list` of `alias`, appending when
+    /// `position` is `None`.
+    pub fn insert_family(
+        &mut self,
+        alias: &str,
+        list: FamilyList,
+        family: &str,
+        position: Option<usize>,
+    ) -> Result<(), EditError> {
+        let entry = self.entry(family)?;
+        if let Entry::Alias(ref target) = entry {
+            if self.reaches(target, alias) {
+                return Err(EditError::AliasCycle {
+                    alias: String::from(alias),
+                    target: target.clone(),
+                });
+            }
+        }
+        self.alias_mut(alias)?.insert_entry(list, entry, position)
+    }
+
+    pub fn remove_family(
+        &mut self,
+        alias: &str,
+        list: FamilyList,
+        family: &str,
+    ) -> Result<(), EditError> {
+        self.alias_mut(alias)?.remove_family(list, family).map(|_| ())
+    }
+
+    pub fn move_family(
+        &mut self,
+        alias: &str,
+        list: FamilyList,
+        family: &str,
+        position: usize,
+    ) -> Result<(), EditError> {
+        self.alias_mut(alias)?.move_family(list, family, position)
+    }
+
+    /// Adds an empty rule to `alias` for patterns asking for `language`.
+    pub fn add_language_rule(&mut self, alias: &str, language: &str) -> Result<(), EditError> {
+        let language = family::normalize_language(language).ok_or_else(|| {
+            EditError::InvalidLanguage(String::from(language))
+        })?;
+        let alias = self.alias_mut(alias)?;
+        if alias.language_rule(&language).is_some() {
+            return Err(EditError::DuplicateLanguageRule {
+                alias: alias.name.clone(),
+                language: language,
+            });
+        }
+        alias.language_rules.push(LanguageRule::new(&language));
+        Ok(())
+    }
+
+    pub fn remove_language_rule(
+        &mut self,
+        alias: &str,
+        language: &str,
+    ) -> Result<LanguageRule<'a>, EditError> {
+        let alias = self.alias_mut(alias)?;
+        let language =
+            family::normalize_language(language).unwrap_or_else(|| String::from(language));
+        match alias.language_rules.iter().position(|x| x.language == language) {
+            Some(index) => Ok(alias.language_rules.remove(index)),
+            None => Err(EditError::UnknownLanguageRule {
+                alias: alias.name.clone(),
+                language: language,
+            }),
+        }
+    }
+
+    /// Inserts an installed family into the `language` rule of `alias`, appending when
+    /// `position` is `None`.
+    pub fn insert_language_family(
+        &mut self,
+        alias: &str,
+        language: &str,
+        family: &str,
+        position: Option<usize>,
+    ) -> Result<(), EditError> {
+        let family = self.family(family)?;
+        let rule = self.language_rule_mut(alias, language)?;
+        let label = format!("{} for {}", alias, rule.language);
+        if rule.position(&family.borrow().name).is_some() {
+            return Err(EditError::DuplicateFamily {
+                alias: label,
+                family: family.borrow().name.clone(),
+            });
+        }
+        let position = position.unwrap_or(rule.families.len());
+        if position > rule.families.len() {
+            return Err(EditError::PositionOutOfBounds {
+                alias: label,
+                position: position,
+            });
+        }
+        rule.families.insert(position, family);
+        Ok(())
+    }
+
+    pub fn remove_language_family(
+        &mut self,
+        alias: &str,
+        language: &str,
+        family: &str,
+    ) -> Result<(), EditError> {
+        let rule = self.language_rule_mut(alias, language)?;
+        let index = rule.position(family).ok_or_else(|| {
+            EditError::FamilyNotInAlias {
+                alias: format!("{} for {}", alias, rule.language),
+                family: String::from(family),
+            }
+        })?;
+        rule.families.remove(index);
+        Ok(())
+    }
 
+    fn language_rule_mut(
+        &mut self,
+        alias: &str,
+        language: &str,
+    ) -> Result<&mut LanguageRule<'a>, EditError> {
+        let language =
+            family::normalize_language(language).unwrap_or_else(|| String::from(language));
+        let alias = self.alias_mut(alias)?;
+        let name = alias.name.clone();
+        alias.language_rule_mut(&language).ok_or_else(|| {
+            EditError::UnknownLanguageRule {
+                alias: name,
+                language: language,
+            }
+        })
+    }
+
+    /// Moves a family from one list to `slot` of another, possibly of another alias. `slot`
+    /// counts positions in the target list as it is before the move, `None` appends.
+    pub fn transfer_family(
+        &mut self,
+        from: (&str, FamilyList),
+        to: (&str, FamilyList),
+        family: &str,
+        slot: Option<usize>,
+    ) -> Result<(), EditError> {
+        // checked before anything changes, so that a failed transfer leaves both lists alone
+        let (index, len) = {
+            let alias = self.alias(from.0)?;
+            let index = alias.position(from.1, family).ok_or_else(|| {
+                EditError::FamilyNotInAlias {
+                    alias: String::from(from.0),
+                    family: String::from(family),
+                }
+            })?;
+            (index, alias.list(from.1).len())
+        };
+        if from != to {
+            self.insert_family(to.0, to.1, family, slot)?;
+            return self.remove_family(from.0, from.1, family);
+        }
+        let slot = slot.unwrap_or(len);
+        let position = if index < slot { slot - 1 } else { slot };
+        self.move_family(from.0, from.1, family, position)
+    }
+
+    /// What the config strips from `family`, if anything.
+    pub fn scan_match(&self, family: &RefCell<Family>) -> Option<&ScanMatch<'a>> {
+        self.scan_matches.iter().find(
+            |x| x.family as *const _ == family as *const _,
+        )
+    }
+
+    /// Whether the config strips `code_point` from `family`.
+    pub fn strips(&self, family: &RefCell<Family>, code_point: u32) -> bool {
+        self.scan_match(family).map_or(false, |x| x.strips(code_point))
+    }
+
+    /// The languages the config takes out of the `lang` property of `family`.
+    pub fn stripped_languages(&self, family: &RefCell<Family>) -> &[String] {
+        self.scan_match(family).map_or(&[][..], |x| &x.stripped_languages[..])
+    }
+
+    /// The scan match of an installed family, added to `scan_matches` if it is not there yet.
+    pub fn add_scan_match(&mut self, family: &str) -> Result<&mut ScanMatch<'a>, EditError> {
+        let family = self.family(family)?;
+        let index = match self.scan_match_position(family.borrow().name.as_str()) {
+            Some(index) => index,
+            None => {
+                self.scan_matches.push(ScanMatch::new(family));
+                self.scan_matches.len() - 1
+            }
+        };
+        Ok(&mut self.scan_matches[index])
+    }
+
+    pub fn strip_range(&mut self, family: &str, range: Range) -> Result<(), EditError> {
+        // custom ranges built by hand are held to the rules of `Range::custom` as well
+        if let Range::Custom {
+            ref name,
+            code_points: (first, last),
+        } = range
+        {
+            Range::custom(name, first, last)?;
+        }
+        self.add_scan_match(family)?.add_range(range)
+    }
+
+    /// Removes a stripped range, dropping the family from `scan_matches` once it strips nothing.
+    pub fn unstrip_range(
+        &mut self,
+        family: &str,
+        name: &str,
+        kind: &str,
+    ) -> Result<Range, EditError> {
+        let index = self.scan_match_position(family).ok_or_else(|| {
+            EditError::UnknownRange {
+                family: String::from(family),
+                range: format!("{}, {}", name, kind),
+            }
+        })?;
+        let range = self.scan_matches[index].remove_range(name, kind)?;
+        self.drop_unused_scan_match(index);
+        Ok(range)
+    }
+
+    /// Keeps fontconfig from choosing the family for `language`, without touching its charset.
+    pub fn strip_language(&mut self, family: &str, language: &str) -> Result<(), EditError> {
+        self.add_scan_match(family)?.add_language(language)
+    }
+
+    pub fn unstrip_language(&mut self, family: &str, language: &str) -> Result<String, EditError> {
+        let index = self.scan_match_position(family).ok_or_else(|| {
+            EditError::UnknownRange {
+                family: String::from(family),
+                range: format!("{}, Language", language),
+            }
+        })?;
+        let language = self.scan_matches[index].remove_language(language)?;
+        self.drop_unused_scan_match(index);
+        Ok(language)
+    }
+
+    fn scan_match_position(&self, family: &str) -> Option<usize> {
+        self.scan_matches.iter().position(
+            |x| x.family.borrow().name == family,
+        )
+    }
+
+    fn drop_unused_scan_match(&mut self, index: usize) {
+        if self.scan_matches[index].strips_nothing() {
+            self.scan_matches.remove(index);
+        }
+    }
+
+    /// Stripped ranges no other family covers any part of, which leaves their code points with
+    /// nothing to fall back to. Empty until charsets are loaded, see
+    /// `deserialization::load_font_info`.
+    pub fn strips_without_fallback(&self) -> Vec<(&'a RefCell<Family>, Range)> {
+        if self.families.iter().all(|x| x.borrow().charset.is_empty()) {
+            return vec![];
+        }
+        let mut result = vec![];
+        for scan_match in &self.scan_matches {
+            let family = scan_match.family;
+            for range in &scan_match.stripped_ranges {
+                let code_points = CodePointSet::from(range);
+                let has_fallback = self.families.iter().any(|x| {
+                    x as *const _ != family as *const _ &&
+                        !self.scan_match(x).map_or(false, |y| {
+                            y.range_position(range.name(), range.kind()).is_some()
+                        }) && x.borrow().count_covered(&code_points) > 0
+                });
+                if !has_fallback {
+                    result.push((family, range.clone()));
+                }
+            }
+        }
+        result
+    }
+
+    /// Hand-written aliases kept in `residue`, see `adopt_alias`.
+    pub fn foreign_aliases(&self) -> Vec<Alias<'a>> {
+        let alias_names = self.aliases.iter().map(|x| x.name.clone()).collect::<Vec<_>>();
+        foreign::aliases(&self.residue, self.families, &self.placeholders, &alias_names)
+            .into_iter()
+            .map(|(_, x)| x)
+            .collect()
+    }
+
+    /// Hand-written scan matches kept in `residue`, see `adopt_scan_match`.
+    pub fn foreign_scan_matches(&self) -> Vec<ForeignScanMatch<'a>> {
+        foreign::scan_matches(&self.residue, self.families, &self.placeholders)
+            .into_iter()
+            .map(|(_, x)| x)
+            .collect()
+    }
+
+    /// Moves the `index`th foreign alias out of `residue` into typeholder management, merging
+    /// it into a typeholder alias of the same name if there is one. Nothing changes when the
+    /// merge fails, or when the move would change the order fontconfig runs it in relative to
+    /// a rule naming the same families, see `foreign::later_conflict`.
+    pub fn adopt_alias(&mut self, index: usize) -> Result<(), EditError> {
+        // the element borrows only `residue`, leaving the aliases free to change
+        let alias_names = self.aliases.iter().map(|x| x.name.clone()).collect::<Vec<_>>();
+        let (element, adopted) =
+            foreign::aliases(&self.residue, self.families, &self.placeholders, &alias_names)
+                .into_iter()
+                .nth(index)
+                .ok_or(EditError::UnknownForeignEntry(index))?;
+        // typeholder aliases are written after the residue, so the alias also moves past the
+        // ones before the alias it is merged into and that alias's language rules
+        let merged = self.aliases.iter().position(|x| x.name == adopted.name);
+        let names = Names::of_alias(&adopted);
+        let passed = &self.aliases[..merged.unwrap_or(self.aliases.len())];
+        let conflict = foreign::later_conflict(&self.residue, element).or_else(|| {
+            passed
+                .iter()
+                .flat_map(|x| vec![Names::of_language_rules(x), Names::of_alias(x)])
+                .chain(merged.map(|x| Names::of_language_rules(&self.aliases[x])))
+                .filter_map(|x| names.shared(&x))
+                .next()
+        });
+        if let Some(family) = conflict {
+            return Err(EditError::AdoptionReorders {
+                entry: adopted.name.clone(),
+                family: family,
+            });
+        }
+        for &list in FAMILY_LISTS.iter() {
+            for entry in adopted.list(list) {
+                if let &Entry::Alias(ref target) = entry {
+                    if self.reaches(target, &adopted.name) {
+                        return Err(EditError::AliasCycle {
+                            alias: adopted.name.clone(),
+                            target: target.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        match merged {
+            Some(i) => {
+                let alias = &mut self.aliases[i];
+                if alias.binding != adopted.binding {
+                    return Err(EditError::BindingMismatch(adopted.name.clone()));
+                }
+                for &list in FAMILY_LISTS.iter() {
+                    if let Some(entry) = adopted.list(list).iter().find(|x| {
+                        alias.position(list, &x.name()).is_some()
+                    })
+                    {
+                        return Err(EditError::DuplicateFamily {
+                            alias: alias.name.clone(),
+                            family: entry.name(),
+                        });
+                    }
+                }
+                for &list in FAMILY_LISTS.iter() {
+                    for entry in adopted.list(list) {
+                        alias.insert_entry(list, entry.clone(), None)?;
+                    }
+                }
+            }
+            None => self.aliases.push(adopted),
+        }
+        foreign::remove(&self.residue, element);
+        Ok(())
+    }
+
+    /// Moves the `index`th foreign scan match out of `residue` into typeholder management.
+    /// Nothing changes when the family already strips one of its ranges, or when a later
+    /// hand-written scan match names the family, see `foreign::later_conflict`.
+    pub fn adopt_scan_match(&mut self, index: usize) -> Result<(), EditError> {
+        let (element, adopted) =
+            foreign::scan_matches(&self.residue, self.families, &self.placeholders)
+                .into_iter()
+                .nth(index)
+                .ok_or(EditError::UnknownForeignEntry(index))?;
+        // the typeholder scan matches it moves past each strip another family
+        if let Some(family) = foreign::later_conflict(&self.residue, element) {
+            return Err(EditError::AdoptionReorders {
+                entry: adopted.family.borrow().name.clone(),
+                family: family,
+            });
+        }
+        if let Some(scan_match) = self.scan_match(adopted.family) {
+            if let Some(range) = adopted.ranges.iter().find(|x| {
+                scan_match.range_position(x.name(), x.kind()).is_some()
+            })
+            {
+                return Err(EditError::DuplicateRange {
+                    family: adopted.family.borrow().name.clone(),
+                    range: format!("{}, {}", range.name(), range.kind()),
+                });
+            }
+        }
+        let position = match self.scan_matches.iter().position(
+            |x| x.family as *const _ == adopted.family as *const _,
+        ) {
+            Some(position) => position,
+            None => {
+                self.scan_matches.push(ScanMatch::new(adopted.family));
+                self.scan_matches.len() - 1
+            }
+        };
+        for range in adopted.ranges {
+            self.scan_matches[position].add_range(range)?;
+        }
+        foreign::remove(&self.residue, element);
+        Ok(())
     }
 }
-*/
 
 
+#[cfg(test)]
+fn test_families() -> Vec<RefCell<Family>> {
+    ["DejaVu Sans", "Noto Sans CJK SC", "Source Han Sans"]
+        .iter()
+        .map(|x| RefCell::new(Family::new(*x)))
+        .collect()
+}
+
 #[test]
-fn test_add_family() {}
+fn test_add_family() {
+    let families = test_families();
+    let placeholders = vec![];
+    let mut config = deserialization::parse(DEFAULT_FONTS_CONF, &families, &placeholders).unwrap();
+
+    let prefer = FamilyList::Prefer;
+
+    config.insert_family("sans-serif", prefer, "DejaVu Sans", None).unwrap();
+    config.insert_family("sans-serif", prefer, "Source Han Sans", None).unwrap();
+    config
+        .insert_family("sans-serif", prefer, "Noto Sans CJK SC", Some(1))
+        .unwrap();
+    assert_eq!(
+        config.alias("sans-serif").unwrap().position(prefer, "Noto Sans CJK SC"),
+        Some(1)
+    );
+
+    assert_eq!(
+        config.insert_family("sans-serif", prefer, "DejaVu Sans", None),
+        Err(EditError::DuplicateFamily {
+            alias: String::from("sans-serif"),
+            family: String::from("DejaVu Sans"),
+        })
+    );
+    assert_eq!(
+        config.insert_family("sans-serif", prefer, "Comic Sans", None),
+        Err(EditError::UnknownFamily(String::from("Comic Sans")))
+    );
+    assert_eq!(
+        config.insert_family("cursive", prefer, "DejaVu Sans", None),
+        Err(EditError::UnknownAlias(String::from("cursive")))
+    );
+
+    config.move_family("sans-serif", prefer, "DejaVu Sans", 2).unwrap();
+    assert_eq!(
+        config.alias("sans-serif").unwrap().position(prefer, "DejaVu Sans"),
+        Some(2)
+    );
+    assert!(
+        config
+            .move_family("sans-serif", prefer, "DejaVu Sans", 3)
+            .is_err()
+    );
+
+    config.remove_family("sans-serif", prefer, "DejaVu Sans").unwrap();
+    assert_eq!(config.alias("sans-serif").unwrap().prefer_list.len(), 2);
+
+    // the same family may appear once in each list
+    config
+        .insert_family("sans-serif", FamilyList::Default, "Source Han Sans", None)
+        .unwrap();
+    assert_eq!(config.alias("sans-serif").unwrap().default_list.len(), 1);
+
+    // dropping behind the last family moves to the end
+    let sans_serif = ("sans-serif", prefer);
+    config
+        .transfer_family(sans_serif, sans_serif, "Noto Sans CJK SC", Some(2))
+        .unwrap();
+    assert_eq!(
+        config.alias("sans-serif").unwrap().position(prefer, "Noto Sans CJK SC"),
+        Some(1)
+    );
+    config
+        .transfer_family(sans_serif, ("serif", prefer), "Noto Sans CJK SC", None)
+        .unwrap();
+    assert_eq!(config.alias("sans-serif").unwrap().prefer_list.len(), 1);
+    assert_eq!(config.alias("serif").unwrap().prefer_list.len(), 1);
+    // a family that is not in the source list is not added to the target either
+    assert!(
+        config
+            .transfer_family(sans_serif, ("serif", prefer), "Noto Sans CJK SC", None)
+            .is_err()
+    );
+    assert_eq!(config.alias("serif").unwrap().prefer_list.len(), 1);
+}
+
+#[test]
+fn test_strip_range() {
+    let families = test_families();
+    let placeholders = vec![];
+    let mut config = deserialization::parse(DEFAULT_FONTS_CONF, &families, &placeholders).unwrap();
+    let han = || {
+        Range::Script {
+            name: String::from("Han"),
+            code_points: vec![(0x4e00, 0x9fff)],
+        }
+    };
+
+    config.strip_range("DejaVu Sans", han()).unwrap();
+    assert!(config.strip_range("DejaVu Sans", han()).is_err());
+    assert_eq!(config.scan_matches.len(), 1);
+    let comma = Range::Custom {
+        name: String::from("CJK, Compatibility"),
+        code_points: (0xf900, 0xfaff),
+    };
+    assert!(config.strip_range("Source Han Sans", comma).is_err());
+    assert_eq!(config.scan_matches.len(), 1);
+
+    assert!(config.strips_without_fallback().is_empty());
+    families[0].borrow_mut().charset = CodePointSet::new(vec![(0x20, 0x7e), (0x4e00, 0x4e0f)]);
+    assert_eq!(config.strips_without_fallback().len(), 1);
+    families[1].borrow_mut().charset = CodePointSet::new(vec![(0x4e00, 0x9fff)]);
+    assert!(config.strips_without_fallback().is_empty());
+
+    config.strip_language("DejaVu Sans", "zh_TW").unwrap();
+    assert!(config.strip_language("DejaVu Sans", "zh-tw").is_err());
+    assert!(config.strip_language("DejaVu Sans", "Chinese").is_err());
+    assert_eq!(
+        config.unstrip_range("DejaVu Sans", "Han", "Script"),
+        Ok(han())
+    );
+    assert_eq!(config.scan_matches.len(), 1);
+    assert_eq!(
+        config.unstrip_language("DejaVu Sans", "zh-TW"),
+        Ok(String::from("zh-tw"))
+    );
+    assert!(config.scan_matches.is_empty());
+}
+
+#[test]
+fn test_alias_entries() {
+    let families = test_families();
+    let placeholders = vec![];
+    let mut config = deserialization::parse(DEFAULT_FONTS_CONF, &families, &placeholders).unwrap();
+    let prefer = FamilyList::Prefer;
+
+    config.add_alias("Brand UI").unwrap();
+    config.add_alias("emoji").unwrap();
+    assert!(config.add_alias("emoji").is_err());
+    assert_eq!(config.add_alias(" "), Err(EditError::EmptyAliasName));
+    assert_eq!(
+        config.rename_alias("emoji", "DejaVu Sans"),
+        Err(EditError::AliasNamesFamily(String::from("DejaVu Sans")))
+    );
+    config.insert_family("Brand UI", prefer, "DejaVu Sans", None).unwrap();
+    config.insert_family("Brand UI", prefer, "sans-serif", None).unwrap();
+    config.insert_family("sans-serif", prefer, "emoji", None).unwrap();
+    assert_eq!(
+        config.alias("Brand UI").unwrap().prefer_list[1],
+        Entry::Alias(String::from("sans-serif"))
+    );
+
+    assert_eq!(
+        config.insert_family("emoji", prefer, "Brand UI", None),
+        Err(EditError::AliasCycle {
+            alias: String::from("emoji"),
+            target: String::from("Brand UI"),
+        })
+    );
+    assert!(config.insert_family("emoji", prefer, "emoji", None).is_err());
+
+    config.rename_alias("sans-serif", "ui-sans-serif").unwrap();
+    assert_eq!(
+        config.alias("Brand UI").unwrap().prefer_list[1],
+        Entry::Alias(String::from("ui-sans-serif"))
+    );
+
+    config.remove_alias("ui-sans-serif").unwrap();
+    assert_eq!(config.alias("Brand UI").unwrap().prefer_list.len(), 1);
+
+    config.add_language_rule("Brand UI", "zh_CN").unwrap();
+    assert!(config.add_language_rule("Brand UI", "zh-cn").is_err());
+    config
+        .insert_language_family("Brand UI", "zh-cn", "Noto Sans CJK SC", None)
+        .unwrap();
+    config
+        .insert_language_family("Brand UI", "zh-cn", "DejaVu Sans", Some(0))
+        .unwrap();
+    assert!(
+        config
+            .insert_language_family("Brand UI", "ja", "DejaVu Sans", None)
+            .is_err()
+    );
+    config
+        .remove_language_family("Brand UI", "zh-cn", "DejaVu Sans")
+        .unwrap();
+    assert_eq!(
+        config.alias("Brand UI").unwrap().language_rules[0].families,
+        vec![&families[1]]
+    );
+    config.remove_language_rule("Brand UI", "zh-CN").unwrap();
+    assert!(config.alias("Brand UI").unwrap().language_rules.is_empty());
+}
@@ -1,22 +1,24 @@
 pub const TYPEHOLDER_COMMENT_PREFIX: &str = " Generated by Typeholder, DO NOT EDIT ";
 
+pub const TYPEHOLDER_DROP_IN_NAME: &str = "50-typeholder.conf";
+
 pub const DEFAULT_FONTS_CONF: &str = "<?xml version='1.0' encoding='UTF-8'?>
 <!DOCTYPE fontconfig SYSTEM 'fonts.dtd'>
 <fontconfig>
+    <!-- Generated by Typeholder, DO NOT EDIT -->
     <alias>
-        <!-- Generated by Typeholder, DO NOT EDIT -->
         <family>sans-serif</family>
         <prefer>
         </prefer>
     </alias>
+    <!-- Generated by Typeholder, DO NOT EDIT -->
     <alias>
-        <!-- Generated by Typeholder, DO NOT EDIT -->
         <family>serif</family>
         <prefer>
         </prefer>
     </alias>
+    <!-- Generated by Typeholder, DO NOT EDIT -->
     <alias>
-        <!-- Generated by Typeholder, DO NOT EDIT -->
         <family>monospace</family>
         <prefer>
         </prefer>
@@ -1,28 +1,39 @@
-use alias::Alias;
+use alias::{Alias, Binding, Entry, LanguageRule, FAMILY_LISTS};
 use config::Config;
 use consts::*;
+use error::ParseError;
 use itertools::Itertools;
 use family::Family;
-use glib::functions;
 use pango::Context;
 use pango::ContextExt;
 use pango::FontMapExt;
 use pango::FontFamilyExt;
-use range::Range;
+use range;
+use range::{CodePointSet, Range};
+use scan_match::ScanMatch;
 use sxd_document::dom::Comment;
 use sxd_document::dom::ChildOfElement;
+use sxd_document::dom::Document;
 use sxd_document::dom::Element;
 use sxd_document::dom::Text;
 use sxd_document::parser;
 
 use std::cell::RefCell;
+use std::cmp;
+use std::env;
+use std::fs;
 use std::fs::File;
-use std::i32;
+use std::io;
 use std::io::Read;
 use std::ops::Deref;
-use std::ops::DerefMut;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
 
 
+/// The longest `path_key`, leaving room for what is appended to it in a 255 byte file name.
+const PATH_KEY_LIMIT: usize = 200;
+
 pub fn list_families(context: &Context) -> Vec<RefCell<Family>> {
     match context.get_font_map() {
         Some(map) => {
@@ -30,46 +41,277 @@ pub fn list_families(context: &Context) -> Vec<RefCell<Family>> {
                 .iter()
                 .filter_map(|x| x.get_name())
                 .filter(|x| !["Sans", "Serif", "Monospace"].contains(&x.as_str()))
-                .map(|x| {
-                    RefCell::new(Family {
-                        name: x,
-                        stripped_ranges: vec![],
-                    })
-                })
+                .map(|x| RefCell::new(Family::new(&x)))
                 .collect()
         }
         None => vec![],
     }
 }
 
-pub fn parse_or_default<'a>(families: &'a Vec<RefCell<Family>>) -> Config<'a> {
-    let fc_config_path = functions::get_user_config_dir()
-        .expect("$XDG_CONFIG_HOME not set!")
-        .join("fontconfig/fonts.conf");
-    let config_parse = match File::open(fc_config_path.as_path()) {
+/// Lists installed families through `fc-list`, for use without a display. Failing to run it is
+/// an error rather than no families, which would make every family look uninstalled.
+pub fn list_installed_families() -> io::Result<Vec<RefCell<Family>>> {
+    let output = Command::new("fc-list")
+        .arg("--format=%{family[0]}\\n")
+        .output()
+        .map_err(|err| {
+            io::Error::new(err.kind(), format!("Failed to run fc-list: {}", err))
+        })?;
+    if !output.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("fc-list failed: {}", String::from_utf8_lossy(&output.stderr).trim()),
+        ));
+    }
+    Ok(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .sorted()
+            .into_iter()
+            .dedup()
+            .map(|x| RefCell::new(Family::new(x)))
+            .collect(),
+    )
+}
+
+/// The family called `name`, installed or one of the `placeholders` of the config.
+pub fn find_family<'a>(
+    families: &'a Vec<RefCell<Family>>,
+    placeholders: &[&'a RefCell<Family>],
+    name: &str,
+) -> Option<&'a RefCell<Family>> {
+    families
+        .iter()
+        .chain(placeholders.iter().cloned())
+        .find(|x| x.borrow().name == name)
+}
+
+/// Placeholders marked not installed for the families `texts` name that are not among
+/// `families`, so that the blocks naming them survive the next save. The caller owns them and
+/// passes them to `parse` along with `families`; configs naming the same family may share one.
+pub fn placeholders(texts: &[&str], families: &Vec<RefCell<Family>>) -> Vec<RefCell<Family>> {
+    let mut names = vec![];
+    for text in texts {
+        if let Ok(package) = parser::parse(text) {
+            if let Some(root) = root_element(package.as_document()) {
+                collect_family_names(root, &mut names);
+            }
+        }
+    }
+    names
+        .into_iter()
+        .filter(|x| !families.iter().any(|y| y.borrow().name == *x))
+        .sorted()
+        .into_iter()
+        .dedup()
+        .map(|x| RefCell::new(Family::not_installed(&x)))
+        .collect()
+}
+
+// the <family>s of alias lists and the <string>s of matches testing or editing the family
+fn collect_family_names(e: Element, names: &mut Vec<String>) {
+    let child_name = match e.name().local_part() {
+        "prefer" | "accept" | "default" => "family",
+        "test" | "edit" if e.attribute_value("name") == Some("family") => "string",
+        _ => "",
+    };
+    names.extend(children_element(child_name, e).filter_map(text).map(
+        |x| String::from(x.text()),
+    ));
+    for child in e.children().into_iter().filter_map(|x| x.element()) {
+        collect_family_names(child, names);
+    }
+}
+
+/// Fills in the monospace flag, styles, languages and charset of `families` from `fc-list`,
+/// merging the faces of each family. Like `list_installed_families`, failing to run it is an
+/// error, since empty charsets would make every strip look like it had no fallback.
+pub fn load_font_info(families: &Vec<RefCell<Family>>) -> io::Result<()> {
+    let output = Command::new("fc-list")
+        .arg("--format=%{family[0]}\t%{spacing}\t%{style[0]}\t%{lang}\t%{charset}\n")
+        .output()
+        .map_err(|err| {
+            io::Error::new(err.kind(), format!("Failed to run fc-list: {}", err))
+        })?;
+    if !output.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("fc-list failed: {}", String::from_utf8_lossy(&output.stderr).trim()),
+        ));
+    }
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let fields = line.split('\t').collect_vec();
+        let (name, spacing, style, languages, charset) = match fields.as_slice() {
+            &[name, spacing, style, languages, charset] => {
+                (name, spacing, style, languages, charset)
+            }
+            _ => continue,
+        };
+        let mut family = match families.iter().find(|x| x.borrow().name == name.trim()) {
+            Some(family) => family.borrow_mut(),
+            None => continue,
+        };
+        // 100 is FC_MONO
+        if spacing == "100" {
+            family.monospace = true;
+        }
+        if !style.is_empty() && !family.styles.iter().any(|x| x == style) {
+            family.styles.push(String::from(style));
+        }
+        for language in languages.split('|').filter(|x| !x.is_empty()) {
+            if !family.languages.iter().any(|x| x == language) {
+                family.languages.push(String::from(language));
+            }
+        }
+        family.charset = family.charset.union(&parse_charset(charset));
+    }
+    for family in families {
+        family.borrow_mut().languages.sort();
+    }
+    Ok(())
+}
+
+/// Parses fontconfig's charset notation, hexadecimal code points and ranges like `20-7e a0`.
+pub fn parse_charset(text: &str) -> CodePointSet {
+    let ranges = text.split_whitespace()
+        .filter_map(|x| {
+            let mut bounds = x.splitn(2, '-').map(|y| u32::from_str_radix(y, 16));
+            match (bounds.next(), bounds.next()) {
+                (Some(Ok(first)), None) => Some((first, first)),
+                (Some(Ok(first)), Some(Ok(last))) => Some((first, last)),
+                _ => None,
+            }
+        })
+        .collect();
+    CodePointSet::new(ranges)
+}
+
+/// Reads the config at `path`, `DEFAULT_FONTS_CONF` when it does not exist yet.
+pub fn read_file(path: &Path) -> io::Result<String> {
+    match File::open(path) {
         Ok(mut f) => {
             let mut buffer = String::new();
-            f.read_to_string(&mut buffer).expect(
-                "Failed to parse your fonts.conf file",
-            );
-            parser::parse(&buffer)
+            f.read_to_string(&mut buffer)?;
+            Ok(buffer)
         }
-        _ => parser::parse(DEFAULT_FONTS_CONF),
-    };
-    let config_package = match config_parse {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+            Ok(String::from(DEFAULT_FONTS_CONF))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// `$HOME`, an error when it is not set.
+pub fn home_dir() -> io::Result<PathBuf> {
+    match env::var_os("HOME") {
+        Some(ref dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+        _ => Err(io::Error::new(io::ErrorKind::NotFound, "$HOME is not set")),
+    }
+}
+
+/// `$XDG_CONFIG_HOME`, falling back to `~/.config` as the XDG Base Directory spec requires.
+pub fn user_config_dir() -> io::Result<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// `$XDG_DATA_HOME`, falling back to `~/.local/share`.
+pub fn user_data_dir() -> io::Result<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// `$XDG_CACHE_HOME`, falling back to `~/.cache`.
+pub fn user_cache_dir() -> io::Result<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+fn xdg_dir(variable: &str, fallback: &str) -> io::Result<PathBuf> {
+    match env::var_os(variable) {
+        Some(ref dir) if Path::new(dir).is_absolute() => Ok(PathBuf::from(dir)),
+        _ => Ok(home_dir()?.join(fallback)),
+    }
+}
+
+/// A file name standing for the config at `path`, to keep state like backups apart per config.
+/// Paths to the same file get the same key, and it stays short enough for a file name.
+pub fn path_key(path: &Path) -> String {
+    let key = canonical_path(path).to_string_lossy().replace('/', "%");
+    if key.len() <= PATH_KEY_LIMIT {
+        return key;
+    }
+    // the end of the path is what tells configs apart to a reader, the FNV-1a hash of the whole
+    // path keeps them apart for sure
+    let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3)
+    });
+    let mut start = key.len() - (PATH_KEY_LIMIT - 17);
+    while !key.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("{:016x}-{}", hash, &key[start..])
+}
+
+// `path` made absolute with `.`, `..` and symlinks resolved, also when the file does not exist
+// yet as long as its directory does
+fn canonical_path(path: &Path) -> PathBuf {
+    if let Ok(path) = fs::canonicalize(path) {
+        return path;
+    }
+    let path = env::current_dir().map(|x| x.join(path)).unwrap_or_else(
+        |_| path.to_path_buf(),
+    );
+    let canonical_parent = path.parent().and_then(|x| fs::canonicalize(x).ok());
+    let file_name = path.file_name().map(|x| x.to_os_string());
+    match (canonical_parent, file_name) {
+        (Some(parent), Some(file_name)) => parent.join(file_name),
+        _ => path,
+    }
+}
+
+pub fn user_config_path() -> io::Result<PathBuf> {
+    Ok(user_config_dir()?.join("fontconfig/fonts.conf"))
+}
+
+/// The conf.d file typeholder owns, picked up by fontconfig's stock `50-user.conf`.
+pub fn drop_in_config_path() -> io::Result<PathBuf> {
+    Ok(user_config_dir()?.join("fontconfig/conf.d").join(
+        TYPEHOLDER_DROP_IN_NAME,
+    ))
+}
+
+/// Parses the fonts.conf `text`. Families that are not installed are looked up in
+/// `placeholders`, see `placeholders`; neither of them is changed.
+pub fn parse<'a, P>(
+    text: &str,
+    families: &'a Vec<RefCell<Family>>,
+    placeholders: P,
+) -> Result<Config<'a>, ParseError>
+where
+    P: IntoIterator<Item = &'a RefCell<Family>>,
+{
+    let placeholders = placeholders.into_iter().collect_vec();
+    let config_package = match parser::parse(text) {
         Ok(package) => package,
-        Err((_, errors)) => panic!("Error parsing fonts.conf!\n{}", errors.iter().join("\n")),
+        Err((position, errors)) => return Err(xml_error(text, position, errors)),
     };
 
     // scan matches collection
-    let mut scan_matches: Vec<&'a RefCell<Family>> = vec![];
+    let mut scan_matches: Vec<ScanMatch<'a>> = vec![];
     // aliases collection
     let mut aliases: Vec<Alias<'a>> = vec![];
+    // language rules collection, written before the alias they belong to
+    let mut language_rules: Vec<(String, LanguageRule<'a>)> = vec![];
 
     {
         let doc = config_package.as_document();
 
-        let old_root_element = doc.root().children()[0].element().expect(INVALID_CONFIG);
+        let old_root_element = doc.root()
+            .children()
+            .into_iter()
+            .filter_map(|x| x.element())
+            .next()
+            .ok_or_else(|| missing_child("#document", "fontconfig"))?;
 
         // rest of dom collection
         let new_root_element = doc.create_element(old_root_element.name());
@@ -81,16 +323,19 @@ pub fn parse_or_default<'a>(families: &'a Vec<RefCell<Family>>) -> Config<'a> {
         for child in old_root_element.children() {
             match child {
                 ChildOfElement::Comment(x) if is_typeholder_comment(x) => {}
+                // top level indentation is regenerated on serialization
+                ChildOfElement::Text(x) if x.text().trim().is_empty() => {}
                 ChildOfElement::Element(x) if prev_is_typeholder_comment(x) => {
                     if x.name().local_part() == "alias" {
-                        aliases.push(parse_alias(x, families));
+                        aliases.push(parse_alias(x, families)?);
                     } else if x.name().local_part() == "match" &&
                                x.attribute_value("target").unwrap_or("") == "scan"
                     {
-                        match update_family(x, families) {
-                            Some(y) => scan_matches.push(y),
-                            _ => {}
-                        }
+                        scan_matches.push(parse_scan_match(x, families, &placeholders)?);
+                    } else if x.name().local_part() == "match" &&
+                               x.attribute_value("target").unwrap_or("") == "pattern"
+                    {
+                        language_rules.push(parse_language_rule(x, families, &placeholders)?);
                     }
                 }
                 x => new_root_element.append_child(x),
@@ -101,15 +346,54 @@ pub fn parse_or_default<'a>(families: &'a Vec<RefCell<Family>>) -> Config<'a> {
         doc.root().append_child(new_root_element);
     }
 
-    Config {
+    for (alias_name, rule) in language_rules {
+        match aliases.iter_mut().find(|x| x.name == alias_name) {
+            Some(alias) => alias.language_rules.push(rule),
+            None => return Err(ParseError::RuleWithoutAlias(alias_name)),
+        }
+    }
+
+    // names that are neither installed nor aliases of this config are uninstalled families
+    let alias_names = aliases.iter().map(|x| x.name.clone()).collect_vec();
+    for alias in &mut aliases {
+        for &list in FAMILY_LISTS.iter() {
+            for entry in alias.list_mut(list).iter_mut() {
+                let family = match entry {
+                    &mut Entry::Alias(ref name) if !alias_names.contains(name) => {
+                        checked_family(families, &placeholders, name)?
+                    }
+                    _ => continue,
+                };
+                *entry = Entry::Family(family);
+            }
+        }
+    }
+
+    Ok(Config {
+        families: families,
+        placeholders: placeholders,
         scan_matches: scan_matches,
         aliases: aliases,
         residue: config_package,
+    })
+}
+
+/// Turns an XML parser failure at byte `position` of `text` into a line and column.
+pub fn xml_error(text: &str, position: usize, errors: Vec<parser::Error>) -> ParseError {
+    let before = &text[..cmp::min(position, text.len())];
+    ParseError::Xml {
+        line: before.matches('\n').count() + 1,
+        column: before.rsplit('\n').next().unwrap_or("").chars().count() + 1,
+        message: errors.iter().join("\n"),
     }
 }
 
-fn prev_is_typeholder_comment(x: Element) -> bool {
-    match x.preceding_siblings().last() {
+/// Whether `x` follows typeholder's comment, that is typeholder wrote it.
+pub fn prev_is_typeholder_comment(x: Element) -> bool {
+    match x.preceding_siblings()
+        .into_iter()
+        .filter(|y| y.text().map_or(true, |z| !z.text().trim().is_empty()))
+        .last() {
         Some(y) => {
             match y.comment() {
                 Some(z) => is_typeholder_comment(z),
@@ -124,118 +408,223 @@ fn is_typeholder_comment(x: Comment) -> bool {
     x.text().starts_with(TYPEHOLDER_COMMENT_PREFIX)
 }
 
-fn update_family<'a>(
+fn parse_scan_match<'a>(
     e: Element,
     families: &'a Vec<RefCell<Family>>,
-) -> Option<&'a RefCell<Family>> {
+    placeholders: &[&'a RefCell<Family>],
+) -> Result<ScanMatch<'a>, ParseError> {
     let family_name = checked_text(checked_child_element(
         "string",
-        checked_child_element("test", e),
-    )).text();
-    let matched_family = families.iter().find(|x| x.borrow().name == family_name);
-    if matched_family.is_some() {
-        let nil_range_template = ("nil", "Custom");
-        let mut current_range_templates = nil_range_template;
-        let charset_elem = checked_child_element(
-            "charset",
-            checked_child_element("minus", checked_child_element("edit", e)),
-        );
-        let ranges = charset_elem
-            .children()
-            .into_iter()
-            .group_by(|x| match x {
-                &ChildOfElement::Comment(y) => {
-                    current_range_templates = y.text()
-                        .splitn(2, ',')
-                        .map(str::trim)
-                        .next_tuple::<(_, _)>()
-                        .expect(INVALID_CONFIG);
-                    current_range_templates
-                }
-                &ChildOfElement::Element(y) if y.name().local_part() == "range" => {
-                    current_range_templates
+        checked_child_element("test", e)?,
+    )?)?
+        .text();
+    let mut scan_match = ScanMatch::new(checked_family(families, placeholders, family_name)?);
+    for edit_elem in children_element("edit", e) {
+        let minus_elem = checked_child_element("minus", edit_elem)?;
+        if edit_elem.attribute_value("name") == Some("lang") {
+            scan_match.stripped_languages =
+                children_element("string", checked_child_element("langset", minus_elem)?)
+                    .map(|x| checked_text(x).map(|y| String::from(y.text().trim())))
+                    .collect::<Result<Vec<String>, ParseError>>()?;
+        } else {
+            scan_match.stripped_ranges =
+                parse_stripped_ranges(checked_child_element("charset", minus_elem)?)?;
+        }
+    }
+    Ok(scan_match)
+}
+
+fn parse_stripped_ranges(charset_elem: Element) -> Result<Vec<Range>, ParseError> {
+    // the <range>s after a comment belong to its Range, those before any to a nil one
+    let mut templated_code_points = vec![];
+    for child in charset_elem.children() {
+        match child {
+            ChildOfElement::Comment(x) => {
+                templated_code_points.push((parse_range_comment(x)?, vec![]))
+            }
+            ChildOfElement::Element(x) if x.name().local_part() == "range" => {
+                let code_points = parse_range(x)?;
+                if templated_code_points.is_empty() {
+                    templated_code_points.push((("nil", "Custom", None), vec![]));
                 }
-                _ => nil_range_template,
-            })
-            .into_iter()
-            .map(|(k, group)| {
-                (
-                    k,
-                    group
-                        .filter_map(|child| child.element())
-                        .filter(|elem| elem.name().local_part() == "range")
-                        .map(|range_elem| {
-                            children_element("int", range_elem)
-                                .map(|int_elem| {
-                                    i32::from_str_radix(&checked_text(int_elem).text()[2..], 16)
-                                        .expect(INVALID_CONFIG)
-                                })
-                                .next_tuple::<(_, _)>()
-                                .expect(INVALID_CONFIG)
-                        })
-                        .collect_vec(),
-                )
-            })
-            .filter(|&(_, ref code_points)| !code_points.is_empty())
-            .coalesce(|mut r0, mut r1| if r0.0 == r1.0 {
-                r0.1.append(&mut r1.1);
-                Ok(r0)
-            } else {
-                Err((r0, r1))
-            })
-            .map(|(k, code_points)| match k.1 {
-                "Block" => Range::Block {
-                    name: String::from(k.0),
-                    code_points: code_points[0],
-                },
-                "Script" => Range::Script {
-                    name: String::from(k.0),
-                    code_points: code_points,
-                },
-                _ => Range::Custom {
-                    name: String::from(k.0),
-                    code_points: code_points[0],
-                },
-            })
-            .collect_vec();
-        matched_family
-            .unwrap()
-            .borrow_mut()
-            .deref_mut()
-            .stripped_ranges = ranges;
+                templated_code_points.last_mut().unwrap().1.push(code_points);
+            }
+            _ => {}
+        }
     }
-    matched_family
+
+    // a comment listing code points stands for a Range whose <range>s were left out where
+    // earlier ranges already strip them, see `serialization::scan_match_element`
+    templated_code_points
+        .into_iter()
+        .map(|((name, kind, listed), written)| (name, kind, listed.unwrap_or(written)))
+        .filter(|&(_, _, ref code_points)| !code_points.is_empty())
+        .map(|(name, kind, code_points)| match kind {
+            // like a comment that names no range, <range>s that would be dropped are an error
+            "Block" | "Custom" if code_points.len() > 1 => Err(ParseError::SeveralRanges {
+                name: String::from(name),
+                kind: String::from(kind),
+            }),
+            "Block" => Ok(Range::Block {
+                name: String::from(name),
+                code_points: code_points[0],
+            }),
+            "Script" => Ok(Range::Script {
+                name: String::from(name),
+                code_points: code_points,
+            }),
+            "Custom" => Ok(Range::Custom {
+                name: String::from(name),
+                code_points: code_points[0],
+            }),
+            _ => Err(ParseError::UnknownRangeKind(String::from(kind))),
+        })
+        .collect()
 }
 
-fn parse_alias<'a>(e: Element, families: &'a Vec<RefCell<Family>>) -> Alias<'a> {
-    let alias_name = checked_text(checked_child_element("family", e)).text();
-    let p_list = children_element("family", checked_child_element("prefer", e))
-        .filter_map(|x| {
-            families.iter().find(|y| {
-                y.borrow().deref().name == checked_text(x).text()
+/// Parses a `name, kind` range comment, optionally followed by the code points of the range
+/// as in `Han, Script, U+2E80..U+2E99 U+2E9B..U+2EF3`.
+fn parse_range_comment<'d>(
+    c: Comment<'d>,
+) -> Result<(&'d str, &'d str, Option<Vec<(u32, u32)>>), ParseError> {
+    let malformed = || ParseError::MalformedRangeComment(String::from(c.text()));
+    let mut fields = c.text().splitn(3, ',').map(str::trim);
+    let (name, kind) = fields.next_tuple::<(_, _)>().ok_or_else(&malformed)?;
+    let listed = match fields.next() {
+        Some(text) => Some(
+            text.split_whitespace()
+                .map(|x| {
+                    let mut bounds = x.splitn(2, "..").map(range::parse_code_point);
+                    match (bounds.next(), bounds.next()) {
+                        (Some(Some(first)), Some(Some(last))) => {
+                            range::bounds(first, last).map_err(|_| malformed())
+                        }
+                        _ => Err(malformed()),
+                    }
+                })
+                .collect::<Result<Vec<(u32, u32)>, ParseError>>()?,
+        ),
+        None => None,
+    };
+    Ok((name, kind, listed))
+}
+
+fn parse_range(e: Element) -> Result<(u32, u32), ParseError> {
+    let (first, last) = children_element("int", e)
+        .map(|int_elem| {
+            let text = checked_text(int_elem)?.text().trim();
+            range::parse_code_point(text).ok_or_else(|| {
+                ParseError::InvalidInt(String::from(text))
             })
         })
-        .collect_vec();
+        .collect::<Result<Vec<u32>, ParseError>>()?
+        .into_iter()
+        .next_tuple::<(_, _)>()
+        .ok_or_else(|| missing_child("range", "int"))?;
+    range::bounds(first, last).map_err(|_| {
+        ParseError::InvalidRange {
+            first: first,
+            last: last,
+        }
+    })
+}
 
-    Alias {
-        name: String::from(alias_name),
-        prefer_list: p_list,
+fn parse_alias<'a>(
+    e: Element,
+    families: &'a Vec<RefCell<Family>>,
+) -> Result<Alias<'a>, ParseError> {
+    let alias_name = checked_text(checked_child_element("family", e)?)?.text();
+    let mut alias = Alias::new(alias_name);
+    if let Some(binding) = e.attribute_value("binding") {
+        alias.binding = Binding::from_name(binding).ok_or_else(|| {
+            ParseError::UnknownBinding(String::from(binding))
+        })?;
+    }
+
+    for &list in FAMILY_LISTS.iter() {
+        let list_elem = match child_element(list.name(), e) {
+            Some(x) => x,
+            None => continue,
+        };
+        for x in children_element("family", list_elem) {
+            let family_name = checked_text(x)?.text();
+            let entry = match families.iter().find(
+                |y| y.borrow().deref().name == family_name,
+            ) {
+                Some(y) => Entry::Family(y),
+                // kept only if it names another alias, see `parse`
+                None => Entry::Alias(String::from(family_name)),
+            };
+            alias.list_mut(list).push(entry);
+        }
     }
+
+    Ok(alias)
 }
 
-fn checked_child_element<'a: 'd, 'd>(name: &'a str, e: Element<'d>) -> Element<'d> {
-    child_element(name, e).expect(&format!(
-        "Element {} has no {} child!",
-        e.name().local_part(),
-        name
-    ))
+/// Parses a `<match target="pattern">` testing `lang` and `family` that prepends families, as
+/// `serialization::language_rule_element` writes it.
+fn parse_language_rule<'a>(
+    e: Element,
+    families: &'a Vec<RefCell<Family>>,
+    placeholders: &[&'a RefCell<Family>],
+) -> Result<(String, LanguageRule<'a>), ParseError> {
+    let mut language = None;
+    let mut alias_name = None;
+    for test_elem in children_element("test", e) {
+        let value = checked_text(checked_child_element("string", test_elem)?)?.text();
+        match test_elem.attribute_value("name") {
+            Some("lang") => language = Some(value),
+            Some("family") => alias_name = Some(value),
+            _ => {}
+        }
+    }
+    let mut rule = LanguageRule::new(language.ok_or_else(|| missing_child("match", "test"))?);
+    for x in children_element("string", checked_child_element("edit", e)?) {
+        rule.families.push(checked_family(families, placeholders, checked_text(x)?.text())?);
+    }
+    let alias_name = alias_name.ok_or_else(|| missing_child("match", "test"))?;
+    Ok((String::from(alias_name), rule))
+}
+
+/// The `<fontconfig>` element of a document; for `Config::residue` that is the rebuilt one.
+pub fn root_element<'d>(doc: Document<'d>) -> Option<Element<'d>> {
+    doc.root()
+        .children()
+        .into_iter()
+        .filter_map(|x| x.element())
+        .last()
+}
+
+fn checked_family<'a>(
+    families: &'a Vec<RefCell<Family>>,
+    placeholders: &[&'a RefCell<Family>],
+    name: &str,
+) -> Result<&'a RefCell<Family>, ParseError> {
+    find_family(families, placeholders, name).ok_or_else(|| {
+        ParseError::MissingPlaceholder(String::from(name))
+    })
+}
+
+fn missing_child(element: &str, child: &str) -> ParseError {
+    ParseError::MissingChild {
+        element: String::from(element),
+        child: String::from(child),
+    }
+}
+
+fn checked_child_element<'a: 'd, 'd>(
+    name: &'a str,
+    e: Element<'d>,
+) -> Result<Element<'d>, ParseError> {
+    child_element(name, e).ok_or_else(|| missing_child(e.name().local_part(), name))
 }
 
-fn child_element<'a: 'd, 'd>(name: &'a str, e: Element<'d>) -> Option<Element<'d>> {
+pub fn child_element<'a: 'd, 'd>(name: &'a str, e: Element<'d>) -> Option<Element<'d>> {
     children_element(name, e).next()
 }
 
-fn children_element<'a: 'd, 'd>(
+pub fn children_element<'a: 'd, 'd>(
     name: &'a str,
     e: Element<'d>,
 ) -> impl Iterator<Item = Element<'d>> + 'd {
@@ -245,10 +634,12 @@ fn children_element<'a: 'd, 'd>(
         .filter(move |x| x.name().local_part() == name)
 }
 
-fn checked_text<'d>(e: Element<'d>) -> Text<'d> {
-    text(e).expect(&format!("Element {} has no text!", e.name().local_part()))
+fn checked_text<'d>(e: Element<'d>) -> Result<Text<'d>, ParseError> {
+    text(e).ok_or_else(|| {
+        ParseError::MissingText { element: String::from(e.name().local_part()) }
+    })
 }
 
-fn text<'d>(e: Element<'d>) -> Option<Text<'d>> {
+pub fn text<'d>(e: Element<'d>) -> Option<Text<'d>> {
     e.children().into_iter().filter_map(|x| x.text()).next()
 }
@@ -0,0 +1,359 @@
+use alias::{Binding, FamilyList, FAMILY_LISTS};
+use deserialization;
+use itertools::Itertools;
+use sxd_document::dom::Element;
+use sxd_document::parser;
+
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[cfg(test)]
+use std::io::Write;
+#[cfg(test)]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+/// Where fontconfig itself starts loading, unless `$FONTCONFIG_FILE` says otherwise.
+pub const SYSTEM_FONTS_CONF: &str = "/etc/fonts/fonts.conf";
+
+#[derive(Debug, PartialEq)]
+pub struct Sourced<T> {
+    pub value: T,
+    pub source: PathBuf,
+}
+
+/// A family of an alias list, with the binding of the `<alias>` that put it there.
+#[derive(Debug, PartialEq)]
+pub struct EffectiveFamily {
+    pub name: String,
+    pub binding: Binding,
+}
+
+/// The families every file adds to an alias, in the order fontconfig tries them.
+#[derive(Debug, PartialEq)]
+pub struct EffectiveAlias {
+    pub name: String,
+    pub prefer_list: Vec<Sourced<EffectiveFamily>>,
+    pub accept_list: Vec<Sourced<EffectiveFamily>>,
+    pub default_list: Vec<Sourced<EffectiveFamily>>,
+}
+
+impl EffectiveAlias {
+    pub fn list(&self, list: FamilyList) -> &Vec<Sourced<EffectiveFamily>> {
+        match list {
+            FamilyList::Prefer => &self.prefer_list,
+            FamilyList::Accept => &self.accept_list,
+            FamilyList::Default => &self.default_list,
+        }
+    }
+
+    fn list_mut(&mut self, list: FamilyList) -> &mut Vec<Sourced<EffectiveFamily>> {
+        match list {
+            FamilyList::Prefer => &mut self.prefer_list,
+            FamilyList::Accept => &mut self.accept_list,
+            FamilyList::Default => &mut self.default_list,
+        }
+    }
+}
+
+/// The read-only result of following every `<include>` from the system fonts.conf.
+#[derive(Debug, Default, PartialEq)]
+pub struct EffectiveConfig {
+    pub files: Vec<PathBuf>,
+    pub font_dirs: Vec<Sourced<PathBuf>>,
+    pub aliases: Vec<EffectiveAlias>,
+    pub scan_matches: Vec<Sourced<String>>,
+    pub problems: Vec<Sourced<String>>,
+}
+
+impl EffectiveConfig {
+    pub fn alias(&self, name: &str) -> Option<&EffectiveAlias> {
+        self.aliases.iter().find(|x| x.name == name)
+    }
+
+    fn alias_mut(&mut self, name: &str) -> &mut EffectiveAlias {
+        let index = self.aliases.iter().position(|x| x.name == name);
+        match index {
+            Some(i) => &mut self.aliases[i],
+            None => {
+                self.aliases.push(EffectiveAlias {
+                    name: String::from(name),
+                    prefer_list: vec![],
+                    accept_list: vec![],
+                    default_list: vec![],
+                });
+                self.aliases.last_mut().unwrap()
+            }
+        }
+    }
+}
+
+pub fn system_config_path() -> PathBuf {
+    match env::var_os("FONTCONFIG_FILE") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(SYSTEM_FONTS_CONF),
+    }
+}
+
+pub fn resolve_system() -> EffectiveConfig {
+    resolve(&system_config_path())
+}
+
+/// Loads `root` and everything it includes, in the order fontconfig would.
+pub fn resolve(root: &Path) -> EffectiveConfig {
+    let mut effective = EffectiveConfig::default();
+    load(root, false, &mut effective);
+    effective
+}
+
+/// The family name, followed by its binding unless that is the default weak one.
+pub fn family_label(family: &EffectiveFamily) -> String {
+    match family.binding {
+        Binding::Weak => family.name.clone(),
+        binding => format!("{} ({} binding)", family.name, binding.name()),
+    }
+}
+
+fn load(path: &Path, ignore_missing: bool, effective: &mut EffectiveConfig) {
+    if effective.files.iter().any(|x| x == path) {
+        return;
+    }
+    if path.is_dir() {
+        return load_dir(path, effective);
+    }
+
+    let mut buffer = String::new();
+    if let Err(err) = File::open(path).and_then(|mut f| f.read_to_string(&mut buffer)) {
+        if !ignore_missing {
+            problem(effective, path, format!("Cannot read config: {}", err));
+        }
+        return;
+    }
+    effective.files.push(path.to_path_buf());
+
+    let package = match parser::parse(&buffer) {
+        Ok(package) => package,
+        Err((_, errors)) => {
+            return problem(effective, path, errors.iter().join("\n"));
+        }
+    };
+    let doc = package.as_document();
+    let root_element = match doc.root().children().into_iter().filter_map(|x| x.element()).next() {
+        Some(e) => e,
+        None => return,
+    };
+
+    for e in root_element.children().into_iter().filter_map(|x| x.element()) {
+        match e.name().local_part() {
+            "include" => {
+                let ignore = e.attribute_value("ignore_missing") == Some("yes");
+                match include_path(e, path) {
+                    Some(included) => load(&included, ignore, effective),
+                    None => problem(effective, path, String::from("Empty <include>")),
+                }
+            }
+            "dir" => {
+                if let Some(dir) = include_path(e, path) {
+                    effective.font_dirs.push(Sourced {
+                        value: dir,
+                        source: path.to_path_buf(),
+                    });
+                }
+            }
+            "alias" => load_alias(e, path, effective),
+            "match" if e.attribute_value("target") == Some("scan") => {
+                if let Some(family) = deserialization::child_element("test", e)
+                    .and_then(|x| deserialization::child_element("string", x))
+                    .and_then(deserialization::text)
+                {
+                    effective.scan_matches.push(Sourced {
+                        value: String::from(family.text()),
+                        source: path.to_path_buf(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// conf.d directories contribute their `[0-9]*.conf` files in name order.
+fn load_dir(dir: &Path, effective: &mut EffectiveConfig) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => return problem(effective, dir, format!("Cannot read directory: {}", err)),
+    };
+    let files = entries
+        .filter_map(|x| x.ok())
+        .map(|x| x.path())
+        .filter(|x| {
+            x.file_name().and_then(|y| y.to_str()).map_or(false, |y| {
+                y.ends_with(".conf") && y.chars().next().map_or(false, |z| z.is_digit(10))
+            })
+        })
+        .sorted();
+    for file in files {
+        load(&file, false, effective);
+    }
+}
+
+fn load_alias(e: Element, path: &Path, effective: &mut EffectiveConfig) {
+    let name = match deserialization::child_element("family", e).and_then(deserialization::text) {
+        Some(name) => String::from(name.text().trim()),
+        None => return problem(effective, path, String::from("<alias> without <family>")),
+    };
+    let binding = match e.attribute_value("binding") {
+        Some(binding) => {
+            match Binding::from_name(binding) {
+                Some(binding) => binding,
+                None => {
+                    let message = format!("Unknown binding \"{}\" of alias \"{}\"", binding, name);
+                    return problem(effective, path, message);
+                }
+            }
+        }
+        None => Binding::Weak,
+    };
+
+    let alias = effective.alias_mut(&name);
+    for &list in FAMILY_LISTS.iter() {
+        let families = deserialization::child_element(list.name(), e)
+            .into_iter()
+            .flat_map(|x| deserialization::children_element("family", x))
+            .filter_map(deserialization::text)
+            .map(|x| {
+                Sourced {
+                    value: EffectiveFamily {
+                        name: String::from(x.text().trim()),
+                        binding: binding,
+                    },
+                    source: path.to_path_buf(),
+                }
+            })
+            .collect_vec();
+
+        // prefer prepends and default appends to the whole list, so earlier files come first
+        // there, but accept goes right behind the alias, in front of what earlier files
+        // accepted. Only the first occurrence of a family matters.
+        let merged = alias.list_mut(list);
+        let mut families = families.into_iter().unique_by(|x| x.value.name.clone()).collect_vec();
+        if list == FamilyList::Accept {
+            merged.retain(|x| !families.iter().any(|y| y.value.name == x.value.name));
+            families.extend(merged.drain(..));
+            *merged = families;
+        } else {
+            for family in families {
+                if !merged.iter().any(|x| x.value.name == family.value.name) {
+                    merged.push(family);
+                }
+            }
+        }
+    }
+}
+
+/// Resolves `<include>`, `<dir>` and `<cachedir>` text the way fontconfig does: `~` is the home
+/// directory, `prefix="xdg"` is relative to `$XDG_CONFIG_HOME` for includes, `$XDG_DATA_HOME`
+/// for font directories and `$XDG_CACHE_HOME` for caches, and other relative paths to the
+/// including file.
+fn include_path(e: Element, including: &Path) -> Option<PathBuf> {
+    let text = deserialization::text(e)
+        .map(|x| x.text().trim())
+        .unwrap_or("");
+    if text.is_empty() {
+        return None;
+    }
+
+    if e.attribute_value("prefix") == Some("xdg") {
+        let base = match e.name().local_part() {
+            "dir" => deserialization::user_data_dir(),
+            "cachedir" => deserialization::user_cache_dir(),
+            _ => deserialization::user_config_dir(),
+        };
+        Some(base.ok()?.join(text))
+    } else if text == "~" || text.starts_with("~/") {
+        Some(deserialization::home_dir().ok()?.join(text[1..].trim_left_matches('/')))
+    } else {
+        let relative = including.parent().unwrap_or(Path::new("/"));
+        Some(relative.join(text))
+    }
+}
+
+fn problem(effective: &mut EffectiveConfig, path: &Path, message: String) {
+    effective.problems.push(Sourced {
+        value: message,
+        source: path.to_path_buf(),
+    });
+}
+
+
+#[test]
+fn test_resolve_includes() {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .subsec_nanos();
+    let dir = env::temp_dir().join(format!("typeholder-effective-{}", nanos));
+    let conf_d = dir.join("conf.d");
+    fs::create_dir_all(&conf_d).unwrap();
+
+    let write = |path: &Path, body: &str| {
+        File::create(path)
+            .unwrap()
+            .write_all(
+                format!("<?xml version='1.0'?><fontconfig>{}</fontconfig>", body).as_bytes(),
+            )
+            .unwrap();
+    };
+    write(
+        &dir.join("fonts.conf"),
+        "<dir>/usr/share/fonts</dir>
+         <dir prefix=\"xdg\">fonts</dir>
+         <include ignore_missing=\"yes\">conf.d</include>
+         <include ignore_missing=\"yes\">missing.conf</include>",
+    );
+    write(
+        &conf_d.join("50-user.conf"),
+        "<alias binding=\"strong\"><family>sans-serif</family>
+         <prefer><family>Noto Sans</family></prefer>
+         <accept><family>Liberation Sans</family></accept></alias>",
+    );
+    write(
+        &conf_d.join("60-latin.conf"),
+        "<alias><family>sans-serif</family>
+         <prefer><family>DejaVu Sans</family><family>Noto Sans</family></prefer>
+         <accept><family>Arimo</family></accept>
+         <default><family>FreeSans</family></default></alias>",
+    );
+    write(&conf_d.join("README"), "<alias><family>ignored</family></alias>");
+
+    let effective = resolve(&dir.join("fonts.conf"));
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(effective.files.len(), 3);
+    assert!(effective.problems.is_empty());
+    assert_eq!(effective.font_dirs[0].value, PathBuf::from("/usr/share/fonts"));
+    assert_eq!(
+        effective.font_dirs[1].value,
+        deserialization::user_data_dir().unwrap().join("fonts")
+    );
+    assert_eq!(effective.aliases.len(), 1);
+    let sans_serif = effective.alias("sans-serif").unwrap();
+    let names = |list| {
+        sans_serif
+            .list(list)
+            .iter()
+            .map(|x: &Sourced<EffectiveFamily>| x.value.name.as_str())
+            .collect_vec()
+    };
+    assert_eq!(names(FamilyList::Prefer), vec!["Noto Sans", "DejaVu Sans"]);
+    assert_eq!(sans_serif.prefer_list[0].value.binding, Binding::Strong);
+    assert_eq!(sans_serif.prefer_list[0].source, conf_d.join("50-user.conf"));
+    assert_eq!(sans_serif.prefer_list[1].value.binding, Binding::Weak);
+    // a later accept goes in front of an earlier one
+    assert_eq!(names(FamilyList::Accept), vec!["Arimo", "Liberation Sans"]);
+    assert_eq!(names(FamilyList::Default), vec!["FreeSans"]);
+}
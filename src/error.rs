@@ -0,0 +1,222 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+
+#[derive(Debug, PartialEq)]
+pub enum EditError {
+    UnknownAlias(String),
+    DuplicateAlias(String),
+    EmptyAliasName,
+    AliasNamesFamily(String),
+    UnknownFamily(String),
+    DuplicateFamily { alias: String, family: String },
+    FamilyNotInAlias { alias: String, family: String },
+    PositionOutOfBounds { alias: String, position: usize },
+    DuplicateRange { family: String, range: String },
+    UnknownRange { family: String, range: String },
+    UnknownForeignEntry(usize),
+    BindingMismatch(String),
+    AliasCycle { alias: String, target: String },
+    AdoptionReorders { entry: String, family: String },
+    InvalidRange { first: u32, last: u32 },
+    InvalidCodePoint(String),
+    InvalidRangeName(String),
+    InvalidLanguage(String),
+    UnknownLanguageRule { alias: String, language: String },
+    DuplicateLanguageRule { alias: String, language: String },
+    Unwritable(String),
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &EditError::UnknownAlias(ref alias) => write!(f, "No alias named \"{}\"", alias),
+            &EditError::DuplicateAlias(ref alias) => {
+                write!(f, "Alias \"{}\" already exists", alias)
+            }
+            &EditError::EmptyAliasName => write!(f, "An alias needs a name"),
+            &EditError::AliasNamesFamily(ref alias) => {
+                write!(f, "\"{}\" is a family, an alias cannot have its name", alias)
+            }
+            &EditError::UnknownFamily(ref family) => {
+                write!(f, "Family \"{}\" is not installed", family)
+            }
+            &EditError::DuplicateFamily {
+                ref alias,
+                ref family,
+            } => write!(f, "Family \"{}\" is already in alias \"{}\"", family, alias),
+            &EditError::FamilyNotInAlias {
+                ref alias,
+                ref family,
+            } => write!(f, "Family \"{}\" is not in alias \"{}\"", family, alias),
+            &EditError::PositionOutOfBounds {
+                ref alias,
+                position,
+            } => write!(f, "Position {} is out of bounds for alias \"{}\"", position, alias),
+            &EditError::DuplicateRange {
+                ref family,
+                ref range,
+            } => write!(f, "Family \"{}\" already strips {}", family, range),
+            &EditError::UnknownRange {
+                ref family,
+                ref range,
+            } => write!(f, "Family \"{}\" does not strip {}", family, range),
+            &EditError::UnknownForeignEntry(index) => {
+                write!(f, "No foreign entry #{}", index)
+            }
+            &EditError::BindingMismatch(ref alias) => {
+                write!(f, "Alias \"{}\" already exists with a different binding", alias)
+            }
+            &EditError::AliasCycle {
+                ref alias,
+                ref target,
+            } => {
+                write!(
+                    f,
+                    "Alias \"{}\" already leads back to \"{}\", adding it would loop",
+                    target,
+                    alias
+                )
+            }
+            &EditError::AdoptionReorders {
+                ref entry,
+                ref family,
+            } => {
+                write!(
+                    f,
+                    "Adopting \"{}\" would move it past a later rule for \"{}\"",
+                    entry,
+                    family
+                )
+            }
+            &EditError::InvalidRange { first, last } => {
+                write!(
+                    f,
+                    "U+{:04X}..U+{:04X} is out of order or beyond U+10FFFF",
+                    first,
+                    last
+                )
+            }
+            &EditError::InvalidCodePoint(ref text) => {
+                write!(f, "\"{}\" is not a code point like U+4E00, 0x4e00 or 19968", text)
+            }
+            &EditError::InvalidRangeName(ref name) => {
+                write!(
+                    f,
+                    "\"{}\" cannot name a range, use no commas or \"--\" and no dash or space \
+                     at either end",
+                    name
+                )
+            }
+            &EditError::InvalidLanguage(ref language) => {
+                write!(f, "\"{}\" is not a language tag like ja or zh-tw", language)
+            }
+            &EditError::UnknownLanguageRule {
+                ref alias,
+                ref language,
+            } => write!(f, "Alias \"{}\" has no rule for language {}", alias, language),
+            &EditError::DuplicateLanguageRule {
+                ref alias,
+                ref language,
+            } => write!(f, "Alias \"{}\" already has a rule for language {}", alias, language),
+            &EditError::Unwritable(ref message) => {
+                write!(f, "Failed to write the config: {}", message)
+            }
+        }
+    }
+}
+
+impl Error for EditError {
+    fn description(&self) -> &str {
+        "Invalid configuration edit"
+    }
+}
+
+
+#[derive(Debug)]
+pub enum ParseError {
+    Io(io::Error),
+    Xml {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    MissingChild { element: String, child: String },
+    MissingText { element: String },
+    InvalidInt(String),
+    InvalidRange { first: u32, last: u32 },
+    MalformedRangeComment(String),
+    UnknownRangeKind(String),
+    SeveralRanges { name: String, kind: String },
+    UnknownBinding(String),
+    RuleWithoutAlias(String),
+    MissingPlaceholder(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ParseError::Io(ref err) => write!(f, "Failed to read fonts.conf: {}", err),
+            &ParseError::Xml {
+                line,
+                column,
+                ref message,
+            } => write!(f, "XML syntax error at {}:{}: {}", line, column, message),
+            &ParseError::MissingChild {
+                ref element,
+                ref child,
+            } => write!(f, "Element <{}> has no <{}> child", element, child),
+            &ParseError::MissingText { ref element } => {
+                write!(f, "Element <{}> has no text", element)
+            }
+            &ParseError::InvalidInt(ref text) => {
+                write!(f, "\"{}\" is not a code point <int> like 0x4e00 or 19968", text)
+            }
+            &ParseError::InvalidRange { first, last } => {
+                write!(
+                    f,
+                    "<range> U+{:04X}..U+{:04X} is out of order or beyond U+10FFFF",
+                    first,
+                    last
+                )
+            }
+            &ParseError::MalformedRangeComment(ref text) => {
+                write!(f, "Range comment \"{}\" is not in the form \"name, kind\"", text)
+            }
+            &ParseError::UnknownRangeKind(ref kind) => {
+                write!(f, "Unknown range kind \"{}\", expected Block, Script or Custom", kind)
+            }
+            &ParseError::SeveralRanges { ref name, ref kind } => {
+                write!(f, "{} range \"{}\" is written as more than one <range>", kind, name)
+            }
+            &ParseError::UnknownBinding(ref binding) => {
+                write!(f, "Unknown binding \"{}\", expected weak, strong or same", binding)
+            }
+            &ParseError::RuleWithoutAlias(ref alias) => {
+                write!(f, "Language rule for \"{}\", which is not a typeholder alias", alias)
+            }
+            &ParseError::MissingPlaceholder(ref family) => {
+                write!(f, "\"{}\" is not installed and has no placeholder", family)
+            }
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn description(&self) -> &str {
+        "Invalid fontconfig configuration"
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(err: io::Error) -> ParseError {
+        ParseError::Io(err)
+    }
+}
+
+impl From<ParseError> for EditError {
+    fn from(err: ParseError) -> EditError {
+        EditError::Unwritable(err.to_string())
+    }
+}
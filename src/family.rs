@@ -1,8 +1,124 @@
-use range::Range;
+use range::{CodePointSet, Range};
 
+use std::cell::RefCell;
 
-#[derive(Debug)]
+
+#[derive(Debug, PartialEq)]
 pub struct Family {
     pub name: String,
-    pub stripped_ranges: Vec<Range>,
+    /// What fontconfig knows about the faces of the family, see
+    /// `deserialization::load_font_info`.
+    pub monospace: bool,
+    pub styles: Vec<String>,
+    pub languages: Vec<String>,
+    /// The code points covered by any face.
+    pub charset: CodePointSet,
+    /// False for the placeholders of families the config names but are not installed, see
+    /// `deserialization::placeholders`.
+    pub installed: bool,
+}
+
+impl Family {
+    pub fn new(name: &str) -> Family {
+        Family {
+            name: String::from(name),
+            monospace: false,
+            styles: vec![],
+            languages: vec![],
+            charset: CodePointSet::default(),
+            installed: true,
+        }
+    }
+
+    pub fn not_installed(name: &str) -> Family {
+        Family {
+            installed: false,
+            ..Family::new(name)
+        }
+    }
+
+    /// The name, flagged when the family is not installed.
+    pub fn label(&self) -> String {
+        if self.installed {
+            self.name.clone()
+        } else {
+            format!("{} (not installed)", self.name)
+        }
+    }
+
+    pub fn covers(&self, code_point: u32) -> bool {
+        self.charset.contains(code_point)
+    }
+
+    /// How well the family suits text in `language`, a normalized tag like `ja` or `zh-tw`, as
+    /// fontconfig ranks it: 0 when it has the language, 1 when it has the language of another
+    /// territory and 2 otherwise. The `stripped` languages don't count.
+    pub fn language_distance(&self, language: &str, stripped: &[String]) -> u32 {
+        let primary = |x: &str| String::from(x.split('-').next().unwrap_or(""));
+        self.languages
+            .iter()
+            .filter(|x| !stripped.contains(x))
+            .map(|x| if x == language {
+                0
+            } else if primary(x) == primary(language) {
+                1
+            } else {
+                2
+            })
+            .min()
+            .unwrap_or(2)
+    }
+
+    /// How many code points of `code_points` the family covers.
+    pub fn count_covered(&self, code_points: &CodePointSet) -> u32 {
+        self.charset.intersection(code_points).len()
+    }
+
+    /// The share of `range` the family covers, from 0 to 1.
+    pub fn coverage_ratio(&self, range: &Range) -> f64 {
+        match range.len() {
+            0 => 0.0,
+            len => self.count_covered(&CodePointSet::from(range)) as f64 / len as f64,
+        }
+    }
+}
+
+/// Whether fontconfig takes `first` and `second` for the same family, as it ignores case and
+/// spaces in family names.
+pub fn same_name(first: &str, second: &str) -> bool {
+    let simplify = |x: &str| {
+        x.chars()
+            .filter(|&x| x != ' ')
+            .flat_map(char::to_lowercase)
+            .collect::<String>()
+    };
+    simplify(first) == simplify(second)
+}
+
+/// Lowercases a language tag and replaces `_` with `-`, or returns `None` for anything not
+/// shaped like a fontconfig language, e.g. `ja`, `zh-tw` or `sr-latn`.
+pub fn normalize_language(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase().replace('_', "-");
+    let valid = {
+        let mut parts = tag.split('-');
+        let language = parts.next().unwrap_or("");
+        let territory = parts.next();
+        language.len() >= 2 && language.len() <= 3 &&
+            language.chars().all(|x| 'a' <= x && x <= 'z') &&
+            territory.map_or(true, |x| {
+                x.len() >= 2 && x.len() <= 4 && x.chars().all(|y| y.is_alphanumeric())
+            }) && parts.next().is_none()
+    };
+    if valid { Some(tag) } else { None }
+}
+
+/// The families whose charset contains `code_point`.
+pub fn families_covering(
+    families: &Vec<RefCell<Family>>,
+    code_point: u32,
+) -> Vec<&RefCell<Family>> {
+    families
+        .iter()
+        .filter(|x| x.borrow().covers(code_point))
+        .collect()
 }
@@ -0,0 +1,333 @@
+use config::Config;
+use deserialization;
+use family;
+use family::Family;
+use itertools::Itertools;
+use range::CodePointSet;
+use serialization;
+use simulation;
+use simulation::Resolved;
+
+use std::cell::RefCell;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+use alias::FamilyList;
+#[cfg(test)]
+use consts::DEFAULT_FONTS_CONF;
+#[cfg(test)]
+use range::Range;
+
+
+/// A fallback font as sorted by fontconfig, with its charset after the scan matches of the
+/// candidate config.
+#[derive(Debug, PartialEq)]
+pub struct SortedFont {
+    pub family: String,
+    pub charset: CodePointSet,
+}
+
+/// A character the simulator and fontconfig disagree about.
+#[derive(Debug, PartialEq)]
+pub struct Difference<'a> {
+    pub character: char,
+    pub simulated: Option<&'a RefCell<Family>>,
+    pub actual: Option<String>,
+}
+
+/// Asks fontconfig for the fallback fonts of `alias` with `config` saved in place of the file at
+/// `config_path`, for text in `language` if given. Rather than calling FcFontSort through the
+/// library, this runs `fc-match -s` with `XDG_CONFIG_HOME` pointing at a directory holding the
+/// candidate. The rest of the user's fontconfig directory and the system configuration apply as
+/// usual, so whatever overrides typeholder's aliases shows up. The sorted fonts are scanned
+/// again with `fc-scan`, since the charsets fontconfig has cached don't know the scan matches
+/// of the candidate yet; so the charsets compared are what `fc-scan` reports, not FcFontSort's,
+/// and only reflect the candidate as long as fontconfig applies `target="scan"` rules while
+/// scanning. Faces `fc-scan` reports nothing for are left out.
+pub fn sort(
+    config: &Config,
+    config_path: &Path,
+    alias: &str,
+    language: Option<&str>,
+) -> io::Result<Vec<SortedFont>> {
+    let pattern = pattern(alias, language);
+    let config_home = create_temp_dir("typeholder-match")?;
+    let result = write_candidate(config, config_path, &config_home).and_then(|()| {
+        let sorted = run(
+            Command::new("fc-match")
+                .arg("-s")
+                .arg("--format=%{file}\\t%{index}\\n")
+                .arg(&pattern)
+                .env("XDG_CONFIG_HOME", &config_home),
+            "fc-match",
+        )?;
+        let files = sorted.lines().filter_map(|x| x.split('\t').next()).unique().collect_vec();
+        if files.is_empty() {
+            return Ok((sorted, String::new()));
+        }
+        let scanned = run(
+            Command::new("fc-scan")
+                .arg("--format=%{file}\\t%{index}\\t%{family[0]}\\t%{charset}\\n")
+                .args(&files)
+                .env("XDG_CONFIG_HOME", &config_home),
+            "fc-scan",
+        )?;
+        Ok((sorted, scanned))
+    });
+    fs::remove_dir_all(&config_home).ok();
+
+    // fontconfig always falls back to some font, so no fonts at all means it did not work
+    let (sorted, scanned) = result?;
+    let sorted = parse_sort_output(&sorted, &scanned);
+    if sorted.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("fc-match sorted no fonts for {}", alias),
+        ));
+    }
+    Ok(sorted)
+}
+
+/// The fontconfig name of a pattern for `alias` in `language`. fontconfig ranks fonts by the
+/// language of the pattern, as the simulation does, and the language rules of the saved config
+/// match on it too.
+fn pattern(alias: &str, language: Option<&str>) -> String {
+    match language {
+        Some(language) => format!("{}:lang={}", escape(alias), escape(language)),
+        None => escape(alias),
+    }
+}
+
+/// Escapes what fontconfig's name syntax reads as separators, so that `sans-serif` stays one
+/// family rather than `sans` at some size.
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '-' | ':' | ',' | '\\' => escaped.push('\\'),
+            _ => {}
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Runs `command`, returning its output or an error with what it wrote to stderr.
+fn run(command: &mut Command, name: &str) -> io::Result<String> {
+    let output = command.output().map_err(|err| {
+        io::Error::new(err.kind(), format!("Failed to run {}: {}", name, err))
+    })?;
+    if !output.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "{} failed with {}: {}",
+                name,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    Ok(String::from(String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Creates a directory of its own under the temporary directory. A name that is already taken,
+/// even by a symlink, is never reused, so removing the directory afterwards is safe.
+fn create_temp_dir(prefix: &str) -> io::Result<PathBuf> {
+    for attempt in 0..100 {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.subsec_nanos())
+            .unwrap_or(0);
+        let dir = env::temp_dir().join(format!("{}-{}-{}", prefix, nanos, attempt));
+        match fs::DirBuilder::new().mode(0o700).create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "Failed to create a temporary directory",
+    ))
+}
+
+/// Simulates `alias` and reports the characters where fontconfig picks another family.
+pub fn compare<'a>(
+    config: &Config<'a>,
+    config_path: &Path,
+    alias: &str,
+    sample: &str,
+    language: Option<&str>,
+) -> Result<Vec<Difference<'a>>, Box<Error>> {
+    let simulated = simulation::simulate(config, alias, sample, language)?;
+    let language = language.and_then(family::normalize_language);
+    let sorted = sort(config, config_path, alias, language.as_ref().map(String::as_str))?;
+    Ok(differences(&simulated, &sorted))
+}
+
+/// Copies the user's fontconfig directory into `config_home` and writes `config` over the file
+/// it replaces.
+fn write_candidate(config: &Config, config_path: &Path, config_home: &Path) -> io::Result<()> {
+    let user_dir = deserialization::user_config_dir()?.join("fontconfig");
+    let temp_dir = config_home.join("fontconfig");
+    for relative in [".", "conf.d"].iter() {
+        let dir = user_dir.join(relative);
+        if !dir.is_dir() {
+            continue;
+        }
+        fs::create_dir_all(temp_dir.join(relative))?;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() {
+                fs::copy(&path, temp_dir.join(relative).join(path.file_name().unwrap()))?;
+            }
+        }
+    }
+
+    // a config outside the user directory stands in for the user's fonts.conf
+    let target = match config_path.strip_prefix(&user_dir) {
+        Ok(relative) => temp_dir.join(relative),
+        Err(_) => temp_dir.join("fonts.conf"),
+    };
+    serialization::write_to(config, &target)
+}
+
+/// Pairs the `file`, `index` lines `fc-match -s` sorted with the faces `fc-scan` reported as
+/// `file`, `index`, `family`, `charset`, keeping the sorted order.
+fn parse_sort_output(sorted: &str, scanned: &str) -> Vec<SortedFont> {
+    let scanned = scanned
+        .lines()
+        .filter_map(|line| {
+            match line.splitn(4, '\t').collect_vec().as_slice() {
+                &[file, index, family, charset] if !family.trim().is_empty() => {
+                    Some(((file, index), (family.trim(), charset)))
+                }
+                _ => None,
+            }
+        })
+        .collect_vec();
+    sorted
+        .lines()
+        .filter_map(|line| {
+            let face = match line.splitn(2, '\t').collect_vec().as_slice() {
+                &[file, index] => (file, index),
+                _ => return None,
+            };
+            let &(_, (family, charset)) = scanned.iter().find(|x| x.0 == face)?;
+            Some(SortedFont {
+                family: String::from(family),
+                charset: deserialization::parse_charset(charset),
+            })
+        })
+        .collect()
+}
+
+/// Compares the simulation with the first sorted font covering each character. The charsets
+/// come from rescanning the fonts, so scan matches elsewhere that undo or add to what the
+/// config strips show up. The languages it strips only change the order once the font cache is
+/// rebuilt, so fontconfig may still rank a font higher for them than the simulation does.
+fn differences<'a>(simulated: &[Resolved<'a>], sorted: &[SortedFont]) -> Vec<Difference<'a>> {
+    simulated
+        .iter()
+        .filter_map(|resolved| {
+            let code_point = resolved.character as u32;
+            let actual = sorted
+                .iter()
+                .find(|font| font.charset.contains(code_point))
+                .map(|font| font.family.clone());
+            let simulated_name = resolved.family.map(|x| x.borrow().name.clone());
+            if simulated_name == actual {
+                None
+            } else {
+                Some(Difference {
+                    character: resolved.character,
+                    simulated: resolved.family,
+                    actual: actual,
+                })
+            }
+        })
+        .collect()
+}
+
+
+#[test]
+fn test_differences() {
+    let families = vec![
+        RefCell::new(Family::new("DejaVu Sans")),
+        RefCell::new(Family::new("Noto Sans CJK SC")),
+    ];
+    families[0].borrow_mut().charset = CodePointSet::new(vec![(0x20, 0x7e), (0x4e00, 0x9fff)]);
+    families[1].borrow_mut().charset = CodePointSet::new(vec![(0x20, 0x7e), (0x4e00, 0x9fff)]);
+    let placeholders = vec![];
+    let mut config = deserialization::parse(DEFAULT_FONTS_CONF, &families, &placeholders).unwrap();
+    config
+        .insert_family("sans-serif", FamilyList::Prefer, "Noto Sans CJK SC", None)
+        .unwrap();
+    config
+        .strip_range(
+            "DejaVu Sans",
+            Range::Custom {
+                name: String::from("CJK"),
+                code_points: (0x4e00, 0x9fff),
+            },
+        )
+        .unwrap();
+
+    // a system rule puts DejaVu Sans first, the rescan took its CJK out
+    let sorted = parse_sort_output(
+        "/fonts/DejaVuSans.ttf\t0\n/fonts/NotoSansCJK.ttc\t2\n",
+        "/fonts/NotoSansCJK.ttc\t0\tNoto Sans CJK JP\t20-7e 4e00-9fff\n\
+         /fonts/NotoSansCJK.ttc\t2\tNoto Sans CJK SC\t20-7e 4e00-9fff\n\
+         /fonts/DejaVuSans.ttf\t0\tDejaVu Sans\t20-7e\n",
+    );
+    assert_eq!(sorted[1].family, "Noto Sans CJK SC");
+    let simulated = simulation::simulate(&config, "sans-serif", "A中", None).unwrap();
+    let expected = Difference {
+        character: 'A',
+        simulated: Some(&families[1]),
+        actual: Some(String::from("DejaVu Sans")),
+    };
+    assert_eq!(differences(&simulated, &sorted), vec![expected]);
+
+    // another scan match that puts the CJK back shows up too
+    let sorted = parse_sort_output(
+        "/fonts/DejaVuSans.ttf\t0\n",
+        "/fonts/DejaVuSans.ttf\t0\tDejaVu Sans\t20-7e 4e00-9fff\n",
+    );
+    assert_eq!(differences(&simulated, &sorted).len(), 2);
+
+    // a sorted face fc-scan has no line for is left out rather than guessed at
+    let sorted = parse_sort_output(
+        "/fonts/Gone.ttf\t0\n/fonts/DejaVuSans.ttf\t0\n/fonts/NotoSansCJK.ttc\t1\n",
+        "/fonts/DejaVuSans.ttf\t0\tDejaVu Sans\t20-7e\n\
+         /fonts/NotoSansCJK.ttc\t0\tNoto Sans CJK JP\t20-7e 4e00-9fff\n",
+    );
+    assert_eq!(
+        sorted,
+        vec![
+            SortedFont {
+                family: String::from("DejaVu Sans"),
+                charset: CodePointSet::new(vec![(0x20, 0x7e)]),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_pattern() {
+    assert_eq!(pattern("sans-serif", None), "sans\\-serif");
+    assert_eq!(
+        pattern("ui-sans-serif", Some("zh-cn")),
+        "ui\\-sans\\-serif:lang=zh\\-cn"
+    );
+    assert_eq!(pattern("a:b,c\\d", None), "a\\:b\\,c\\\\d");
+}
@@ -0,0 +1,565 @@
+use alias::{Alias, Binding, Entry, FAMILY_LISTS};
+use deserialization;
+use family;
+use family::Family;
+use itertools::Itertools;
+use range;
+use range::Range;
+use sxd_document::Package;
+use sxd_document::dom::ChildOfElement;
+use sxd_document::dom::Element;
+
+use std::cell::RefCell;
+
+#[cfg(test)]
+use error::EditError;
+#[cfg(test)]
+use serialization;
+
+
+/// A hand-written `<match target="scan">` stripping charset ranges from one family.
+#[derive(Debug, PartialEq)]
+pub struct ForeignScanMatch<'a> {
+    pub family: &'a RefCell<Family>,
+    pub ranges: Vec<Range>,
+}
+
+/// The families and aliases a rule is run for, and the ones it puts into patterns. Rules of one
+/// pass that share none of them, other than both adding a family, can swap places.
+#[derive(Debug, Default)]
+pub struct Names {
+    pub tested: Vec<String>,
+    pub added: Vec<String>,
+}
+
+impl Names {
+    /// What `alias` is run for and adds, leaving out its language rules.
+    pub fn of_alias(alias: &Alias) -> Names {
+        Names {
+            tested: vec![alias.name.clone()],
+            added: FAMILY_LISTS
+                .iter()
+                .flat_map(|&x| alias.list(x).iter().map(Entry::name))
+                .collect(),
+        }
+    }
+
+    /// What the language rules of `alias` are run for and add.
+    pub fn of_language_rules(alias: &Alias) -> Names {
+        Names {
+            tested: if alias.language_rules.is_empty() {
+                vec![]
+            } else {
+                vec![alias.name.clone()]
+            },
+            added: alias
+                .language_rules
+                .iter()
+                .flat_map(|x| x.families.iter().map(|y| y.borrow().name.clone()))
+                .collect(),
+        }
+    }
+
+    /// A name that keeps the two rules from swapping places, one of them testing it.
+    pub fn shared(&self, other: &Names) -> Option<String> {
+        let contains = |names: &[String], name: &str| {
+            names.iter().any(|x| family::same_name(x, name))
+        };
+        self.tested
+            .iter()
+            .find(|x| contains(&other.tested, x) || contains(&other.added, x))
+            .or_else(|| self.added.iter().find(|x| contains(&other.tested, x)))
+            .cloned()
+    }
+}
+
+/// Hand-written `<alias>`es in `residue` that typeholder understands, each with its element.
+/// List members that are neither installed nor named by `alias_names` or another hand-written
+/// alias are looked up in `placeholders`.
+pub fn aliases<'a, 'd>(
+    residue: &'d Package,
+    families: &'a Vec<RefCell<Family>>,
+    placeholders: &[&'a RefCell<Family>],
+    alias_names: &[String],
+) -> Vec<(Element<'d>, Alias<'a>)> {
+    let doc = residue.as_document();
+    let elements = top_level_elements(deserialization::root_element(doc));
+    let mut alias_names = alias_names.to_vec();
+    alias_names.extend(elements.iter().filter_map(|&e| alias_name(e)));
+    elements
+        .into_iter()
+        .filter_map(|e| {
+            parse_alias(e, families, placeholders, &alias_names).map(|x| (e, x))
+        })
+        .collect()
+}
+
+/// Hand-written `<match target="scan">`es in `residue` that typeholder understands, each with
+/// its element.
+pub fn scan_matches<'a, 'd>(
+    residue: &'d Package,
+    families: &'a Vec<RefCell<Family>>,
+    placeholders: &[&'a RefCell<Family>],
+) -> Vec<(Element<'d>, ForeignScanMatch<'a>)> {
+    let doc = residue.as_document();
+    top_level_elements(deserialization::root_element(doc))
+        .into_iter()
+        .filter_map(|e| parse_scan_match(e, families, placeholders).map(|x| (e, x)))
+        .collect()
+}
+
+/// Removes `e`, one of the elements `aliases` or `scan_matches` list, from `residue`.
+pub fn remove(residue: &Package, e: Element) {
+    if let Some(root_element) = deserialization::root_element(residue.as_document()) {
+        root_element.remove_child(e);
+    }
+}
+
+/// A name `e`, one of the elements `aliases` or `scan_matches` list, shares with a
+/// hand-written element after it that fontconfig runs in the same pass. Adopting moves it
+/// after all of them, so it can't be adopted without changing what either does. `<include>`d
+/// files are not looked into.
+pub fn later_conflict(residue: &Package, e: Element) -> Option<String> {
+    let elements = top_level_elements(deserialization::root_element(residue.as_document()));
+    let position = elements.iter().position(|&x| x == e)?;
+    let pass = rule_pass(e);
+    let names = element_names(e);
+    elements[position + 1..]
+        .iter()
+        .filter(|&&x| rule_pass(x) == pass)
+        .filter_map(|&x| names.shared(&element_names(x)))
+        .next()
+}
+
+// The rules fontconfig runs `e` with, in document order: "pattern", "font" or "scan".
+fn rule_pass<'d>(e: Element<'d>) -> Option<&'d str> {
+    match e.name().local_part() {
+        "alias" => Some("pattern"),
+        "match" => Some(e.attribute_value("target").unwrap_or("pattern")),
+        _ => None,
+    }
+}
+
+// the <family>s of an <alias> and of its lists, or the family names a <match> tests and edits
+fn element_names(e: Element) -> Names {
+    let texts = |name, parent| {
+        deserialization::children_element(name, parent)
+            .filter_map(deserialization::text)
+            .map(|x| String::from(x.text()))
+            .collect_vec()
+    };
+    let mut names = Names::default();
+    let is_alias = e.name().local_part() == "alias";
+    if is_alias {
+        names.tested = texts("family", e);
+    }
+    for child in e.children().into_iter().filter_map(|x| x.element()) {
+        let is_family = child.attribute_value("name") == Some("family");
+        match child.name().local_part() {
+            "prefer" | "accept" | "default" if is_alias => {
+                names.added.extend(texts("family", child))
+            }
+            "test" if is_family && !is_alias => names.tested.extend(texts("string", child)),
+            "edit" if is_family && !is_alias => names.added.extend(texts("string", child)),
+            _ => {}
+        }
+    }
+    names
+}
+
+fn top_level_elements<'d>(root_element: Option<Element<'d>>) -> Vec<Element<'d>> {
+    root_element
+        .map(|x| x.children())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|x| x.element())
+        .collect()
+}
+
+// Only elements that typeholder writes back exactly the same way are understood: anything
+// else in them, be it an attribute, a comment or a second <family>, would be lost on adoption.
+// Being written back after the residue is what `later_conflict` checks for.
+
+fn alias_name(e: Element) -> Option<String> {
+    if e.name().local_part() == "alias" {
+        deserialization::child_element("family", e).and_then(plain_text)
+    } else {
+        None
+    }
+}
+
+fn is_understood_alias(e: Element) -> bool {
+    if e.name().local_part() != "alias" || !has_only_attributes(e, &["binding"]) ||
+        !has_only_children(e, &["family", "prefer", "accept", "default"])
+    {
+        return false;
+    }
+    if let Some(binding) = e.attribute_value("binding") {
+        if Binding::from_name(binding).is_none() {
+            return false;
+        }
+    }
+    if deserialization::children_element("family", e).count() != 1 || alias_name(e).is_none() {
+        return false;
+    }
+    FAMILY_LISTS.iter().all(|list| {
+        let list_elems = deserialization::children_element(list.name(), e).collect_vec();
+        match list_elems.as_slice() {
+            &[] => true,
+            &[list_elem] => {
+                let names = deserialization::children_element("family", list_elem)
+                    .map(plain_text)
+                    .collect::<Option<Vec<String>>>();
+                has_only_attributes(list_elem, &[]) &&
+                    has_only_children(list_elem, &["family"]) &&
+                    names.map_or(false, |x| x.len() == x.iter().unique().count())
+            }
+            _ => false,
+        }
+    })
+}
+
+fn parse_alias<'a>(
+    e: Element,
+    families: &'a Vec<RefCell<Family>>,
+    placeholders: &[&'a RefCell<Family>],
+    alias_names: &[String],
+) -> Option<Alias<'a>> {
+    if !is_understood_alias(e) {
+        return None;
+    }
+    let mut alias = Alias::new(&alias_name(e)?);
+    if let Some(binding) = e.attribute_value("binding") {
+        alias.binding = Binding::from_name(binding)?;
+    }
+
+    for &list in FAMILY_LISTS.iter() {
+        let list_elem = match deserialization::child_element(list.name(), e) {
+            Some(x) => x,
+            None => continue,
+        };
+        for name in deserialization::children_element("family", list_elem).filter_map(plain_text) {
+            let entry = if families.iter().any(|y| y.borrow().name == name) ||
+                !alias_names.contains(&name)
+            {
+                Entry::Family(deserialization::find_family(families, placeholders, &name)?)
+            } else {
+                Entry::Alias(name)
+            };
+            alias.list_mut(list).push(entry);
+        }
+    }
+
+    Some(alias)
+}
+
+fn parse_scan_match<'a>(
+    e: Element,
+    families: &'a Vec<RefCell<Family>>,
+    placeholders: &[&'a RefCell<Family>],
+) -> Option<ForeignScanMatch<'a>> {
+    let (family_name, code_points) = scan_match_parts(e)?;
+    let mut ranges = vec![];
+    for (first, last) in code_points {
+        let name = format!("U+{:04X}..U+{:04X}", first, last);
+        let range = Range::custom(&name, first, last).ok()?;
+        if ranges.contains(&range) {
+            return None;
+        }
+        ranges.push(range);
+    }
+
+    Some(ForeignScanMatch {
+        family: deserialization::find_family(families, placeholders, &family_name)?,
+        ranges: ranges,
+    })
+}
+
+// the family name and the stripped code points of a scan match, as in
+// <match target="scan"><test name="family"><string>...</string></test>
+// <edit name="charset" mode="assign"><minus><name>charset</name><charset>...</charset></minus>
+// </edit></match>
+fn scan_match_parts(e: Element) -> Option<(String, Vec<(u32, u32)>)> {
+    if e.name().local_part() != "match" || e.attribute_value("target") != Some("scan") ||
+        !has_only_attributes(e, &["target"]) || !has_only_children(e, &["test", "edit"])
+    {
+        return None;
+    }
+    let test_elem = only_child("test", e)?;
+    if test_elem.attribute_value("name") != Some("family") ||
+        !has_only_attributes(test_elem, &["name"]) ||
+        !has_only_children(test_elem, &["string"])
+    {
+        return None;
+    }
+    let family_name = plain_text(only_child("string", test_elem)?)?;
+
+    let edit_elem = only_child("edit", e)?;
+    if edit_elem.attribute_value("name") != Some("charset") ||
+        edit_elem.attribute_value("mode").map_or(false, |x| x != "assign") ||
+        !has_only_attributes(edit_elem, &["name", "mode"]) ||
+        !has_only_children(edit_elem, &["minus"])
+    {
+        return None;
+    }
+    let minus_elem = only_child("minus", edit_elem)?;
+    if !has_only_attributes(minus_elem, &[]) ||
+        !has_only_children(minus_elem, &["name", "charset"]) ||
+        only_child("name", minus_elem).and_then(plain_text) != Some(String::from("charset"))
+    {
+        return None;
+    }
+    let charset_elem = only_child("charset", minus_elem)?;
+    if !has_only_attributes(charset_elem, &[]) ||
+        !has_only_children(charset_elem, &["range", "int"])
+    {
+        return None;
+    }
+
+    // fontconfig allows both <range><int/><int/></range> and single <int/> code points
+    let mut code_points = vec![];
+    for child in charset_elem.children() {
+        code_points.push(match child {
+            ChildOfElement::Element(x) if x.name().local_part() == "range" => {
+                if !has_only_attributes(x, &[]) || !has_only_children(x, &["int"]) {
+                    return None;
+                }
+                let ints = deserialization::children_element("int", x)
+                    .map(parse_int)
+                    .collect::<Option<Vec<u32>>>()?;
+                match ints.as_slice() {
+                    &[first, last] => (first, last),
+                    _ => return None,
+                }
+            }
+            ChildOfElement::Element(x) => {
+                let code_point = parse_int(x)?;
+                (code_point, code_point)
+            }
+            _ => continue,
+        });
+    }
+    Some((family_name, code_points))
+}
+
+/// The only child of `e` called `name`.
+fn only_child<'d>(name: &'static str, e: Element<'d>) -> Option<Element<'d>> {
+    let mut children = deserialization::children_element(name, e);
+    match (children.next(), children.next()) {
+        (Some(x), None) => Some(x),
+        _ => None,
+    }
+}
+
+fn has_only_attributes(e: Element, names: &[&str]) -> bool {
+    e.attributes().iter().all(|x| {
+        x.name().namespace_uri().is_none() && names.contains(&x.name().local_part())
+    })
+}
+
+/// Whether `e` has nothing but elements from `names` and whitespace in it.
+fn has_only_children(e: Element, names: &[&str]) -> bool {
+    e.children().into_iter().all(|x| match x {
+        ChildOfElement::Element(x) => {
+            x.name().namespace_uri().is_none() && names.contains(&x.name().local_part())
+        }
+        ChildOfElement::Text(x) => x.text().trim().is_empty(),
+        _ => false,
+    })
+}
+
+/// The text of an element holding nothing else, as long as it needs no trimming.
+fn plain_text(e: Element) -> Option<String> {
+    let children = e.children();
+    match children.as_slice() {
+        &[ChildOfElement::Text(x)] if !x.text().is_empty() && x.text().trim() == x.text() => {
+            if e.attributes().is_empty() {
+                Some(String::from(x.text()))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+fn parse_int(e: Element) -> Option<u32> {
+    range::parse_code_point(&plain_text(e)?)
+}
+
+
+#[test]
+fn test_adopt_foreign() {
+    let fonts_conf = "<?xml version='1.0'?>
+<fontconfig>
+    <alias>
+        <family>sans-serif</family>
+        <prefer><family>DejaVu Sans</family></prefer>
+    </alias>
+    <alias>
+        <family>sans-serif</family>
+        <prefer><family>DejaVu Sans</family><family>Not Installed</family></prefer>
+    </alias>
+    <alias>
+        <family>serif</family>
+        <family>monospace</family>
+        <prefer><family>DejaVu Sans</family></prefer>
+    </alias>
+    <match target=\"scan\">
+        <test name=\"family\"><string>DejaVu Sans</string></test>
+        <edit name=\"charset\" mode=\"append\">
+            <minus><name>charset</name><charset><int>0x3001</int></charset></minus>
+        </edit>
+    </match>
+    <match target=\"scan\">
+        <test name=\"family\"><string>DejaVu Sans</string></test>
+        <edit name=\"charset\" mode=\"assign\">
+            <minus>
+                <name>charset</name>
+                <charset>
+                    <range><int>0x4e00</int><int>0x9fff</int></range>
+                    <int>12288</int>
+                </charset>
+            </minus>
+        </edit>
+    </match>
+</fontconfig>
+";
+    let families = vec![RefCell::new(Family::new("DejaVu Sans"))];
+    let placeholders = deserialization::placeholders(&[fonts_conf], &families);
+    let mut config = deserialization::parse(fonts_conf, &families, &placeholders).unwrap();
+    assert!(config.aliases.is_empty());
+    // an alias for two families and an appending edit would not be written back the same
+    assert_eq!(config.foreign_aliases().len(), 2);
+    assert_eq!(config.foreign_scan_matches().len(), 1);
+    let prefer_list = config.foreign_aliases().remove(1).prefer_list;
+    assert_eq!(prefer_list.len(), 2);
+    assert!(!prefer_list[1].family().unwrap().borrow().installed);
+    assert_eq!(
+        config.foreign_scan_matches()[0].ranges,
+        vec![
+            Range::Custom {
+                name: String::from("U+4E00..U+9FFF"),
+                code_points: (0x4e00, 0x9fff),
+            },
+            Range::Custom {
+                name: String::from("U+3000..U+3000"),
+                code_points: (0x3000, 0x3000),
+            },
+        ]
+    );
+
+    // the first sans-serif would end up after the second one
+    assert!(config.adopt_alias(0).is_err());
+    config.adopt_alias(1).unwrap();
+    config.adopt_scan_match(0).unwrap();
+    assert_eq!(config.aliases[0].prefer_list, prefer_list);
+    // merging the first sans-serif would drop its DejaVu Sans, so it stays where it is
+    assert!(config.adopt_alias(0).is_err());
+    assert_eq!(config.foreign_aliases().len(), 1);
+    assert!(config.adopt_scan_match(0).is_err());
+    assert!(config.foreign_scan_matches().is_empty());
+
+    let adopted_families = vec![RefCell::new(Family::new("DejaVu Sans"))];
+    let text = serialization::to_string(&config).unwrap();
+    let adopted_placeholders = deserialization::placeholders(&[&text], &adopted_families);
+    let adopted = deserialization::parse(&text, &adopted_families, &adopted_placeholders)
+        .unwrap();
+    assert_eq!(adopted.aliases[0].name, "sans-serif");
+    assert_eq!(adopted.aliases[0].prefer_list[1].name(), "Not Installed");
+    assert_eq!(adopted.scan_matches[0].stripped_ranges.len(), 2);
+    assert_eq!(adopted.foreign_aliases().len(), 1);
+    let residue = serialization::format_package(&adopted.residue);
+    assert!(residue.contains("<family>monospace</family>"));
+    assert!(residue.contains("append"));
+}
+
+#[test]
+fn test_adopt_in_order() {
+    let fonts_conf = "<?xml version='1.0'?>
+<fontconfig>
+    <match>
+        <test name=\"family\"><string>serif</string></test>
+        <edit name=\"family\" mode=\"prepend\"><string>DejaVu Serif</string></edit>
+    </match>
+    <alias>
+        <family>sans-serif</family>
+        <prefer><family>DejaVu Sans</family></prefer>
+    </alias>
+    <match target=\"font\">
+        <test name=\"family\"><string>DejaVu Sans</string></test>
+        <edit name=\"antialias\"><bool>false</bool></edit>
+    </match>
+    <alias>
+        <family>monospace</family>
+        <prefer><family>DejaVu Sans Mono</family></prefer>
+    </alias>
+    <match>
+        <test name=\"family\"><string>dejavusansmono</string></test>
+        <edit name=\"family\" mode=\"append\"><string>Noto Sans Mono</string></edit>
+    </match>
+</fontconfig>
+";
+    let families = vec![
+        RefCell::new(Family::new("DejaVu Sans")),
+        RefCell::new(Family::new("DejaVu Sans Mono")),
+    ];
+    let placeholders = deserialization::placeholders(&[fonts_conf], &families);
+    let mut config = deserialization::parse(fonts_conf, &families, &placeholders).unwrap();
+    // the last match goes on from what monospace puts in, fontconfig ignoring case and spaces
+    match config.adopt_alias(1) {
+        Err(EditError::AdoptionReorders { entry, family }) => {
+            assert_eq!((entry.as_str(), family.as_str()), ("monospace", "DejaVu Sans Mono"));
+        }
+        x => panic!("{:?}", x),
+    }
+    assert_eq!(config.foreign_aliases().len(), 2);
+
+    // the matches around sans-serif name other families or run in another pass
+    config.adopt_alias(0).unwrap();
+    let text = serialization::to_string(&config).unwrap();
+    let positions = ["<string>serif", "<bool>", "<family>monospace", "<family>sans-serif"]
+        .iter()
+        .map(|x| text.find(x).unwrap())
+        .collect_vec();
+    assert!(positions.windows(2).all(|x| x[0] < x[1]));
+}
+
+#[test]
+fn test_adopt_after_malformed() {
+    let fonts_conf = "<?xml version='1.0'?>
+<fontconfig>
+    <match target=\"scan\">
+        <test name=\"family\"><string>DejaVu Sans</string></test>
+        <edit name=\"charset\" mode=\"assign\">
+            <minus>
+                <name>charset</name>
+                <charset><range><int>0x9fff</int><int>0x4e00</int></range></charset>
+            </minus>
+        </edit>
+    </match>
+    <match target=\"scan\">
+        <test name=\"family\"><string>Noto Sans</string></test>
+        <edit name=\"charset\" mode=\"assign\">
+            <minus><name>charset</name><charset><int>0x3000</int></charset></minus>
+        </edit>
+    </match>
+</fontconfig>
+";
+    let families = vec![
+        RefCell::new(Family::new("DejaVu Sans")),
+        RefCell::new(Family::new("Noto Sans")),
+    ];
+    let placeholders = deserialization::placeholders(&[fonts_conf], &families);
+    let mut config = deserialization::parse(fonts_conf, &families, &placeholders).unwrap();
+    // the reversed range is not understood, so only the second match can be adopted
+    assert_eq!(config.foreign_scan_matches().len(), 1);
+    config.adopt_scan_match(0).unwrap();
+    assert!(config.foreign_scan_matches().is_empty());
+
+    let residue = serialization::format_package(&config.residue);
+    assert!(residue.contains("0x9fff"));
+    assert!(!residue.contains("0x3000"));
+    let text = serialization::to_string(&config).unwrap();
+    assert_eq!(text.matches("Noto Sans").count(), 1);
+}
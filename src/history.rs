@@ -0,0 +1,342 @@
+use config::Config;
+use deserialization;
+use error::{EditError, ParseError};
+use serialization;
+
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[cfg(test)]
+use consts::DEFAULT_FONTS_CONF;
+#[cfg(test)]
+use family::Family;
+#[cfg(test)]
+use std::cell::RefCell;
+
+
+/// How many edits are kept, older ones can no longer be undone.
+const HISTORY_LIMIT: usize = 100;
+
+const JOURNAL_HEADER: &str = "typeholder journal 1\n";
+
+/// One undoable edit, kept as the config text before and after it. Snapshots rather than
+/// inverse operations keep every edit, including adopting foreign entries, undoable alike.
+pub struct Edit {
+    pub description: String,
+    pub before: String,
+    pub after: String,
+    id: usize,
+}
+
+/// The undo and redo stacks of a config, and which edit was last saved.
+pub struct History {
+    undo_stack: Vec<Edit>,
+    redo_stack: Vec<Edit>,
+    next_id: usize,
+    saved_id: Option<usize>,
+    on_change: Option<Box<Fn(&History)>>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            undo_stack: vec![],
+            redo_stack: vec![],
+            next_id: 0,
+            saved_id: None,
+            on_change: None,
+        }
+    }
+
+    /// Calls `f` whenever an edit is recorded, undone or redone, or the config is saved.
+    pub fn connect_changed<F: Fn(&History) + 'static>(&mut self, f: F) {
+        self.on_change = Some(Box::new(f));
+    }
+
+    /// Runs `edit` on `config`, recording it when it succeeds and changes anything. A failed
+    /// edit leaves `config` as it was.
+    pub fn apply<'a, T, F>(
+        &mut self,
+        config: &mut Config<'a>,
+        description: &str,
+        edit: F,
+    ) -> Result<T, EditError>
+    where
+        F: FnOnce(&mut Config<'a>) -> Result<T, EditError>,
+    {
+        let before = serialization::to_string(config)?;
+        let result = edit(config).and_then(|result| {
+            let after = serialization::to_string(config)?;
+            Ok((result, after))
+        });
+        match result {
+            Ok((result, after)) => {
+                self.record(description, before, after);
+                Ok(result)
+            }
+            Err(err) => {
+                // an edit failing half way is rolled back, the history has nothing to undo it by
+                if serialization::to_string(config).ok().as_ref() != Some(&before) {
+                    let placeholders = config.placeholders.clone();
+                    *config = deserialization::parse(&before, config.families, placeholders)
+                        .expect("Failed to roll back a failed edit!");
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Records an edit that turned `before` into `after`, forgetting what could be redone.
+    /// Edits that change nothing are ignored.
+    pub fn record(&mut self, description: &str, before: String, after: String) {
+        if before == after {
+            return;
+        }
+        self.push_undo(String::from(description), before, after);
+        self.redo_stack.clear();
+        if self.undo_stack.len() > HISTORY_LIMIT {
+            self.undo_stack.remove(0);
+        }
+        self.changed();
+    }
+
+    /// Reverts the last edit of `config`, returning its description, or `None` when there is
+    /// nothing to undo.
+    pub fn undo<'a>(&mut self, config: &mut Config<'a>) -> Result<Option<String>, ParseError> {
+        let restored = match self.undo_stack.last() {
+            Some(edit) => {
+                deserialization::parse(&edit.before, config.families, config.placeholders.clone())?
+            }
+            None => return Ok(None),
+        };
+        *config = restored;
+        let edit = self.undo_edit().unwrap();
+        let description = edit.description.clone();
+        self.changed();
+        Ok(Some(description))
+    }
+
+    /// Applies the last undone edit to `config` again.
+    pub fn redo<'a>(&mut self, config: &mut Config<'a>) -> Result<Option<String>, ParseError> {
+        let restored = match self.redo_stack.last() {
+            Some(edit) => {
+                deserialization::parse(&edit.after, config.families, config.placeholders.clone())?
+            }
+            None => return Ok(None),
+        };
+        *config = restored;
+        let edit = self.redo_edit().unwrap();
+        let description = edit.description.clone();
+        self.changed();
+        Ok(Some(description))
+    }
+
+    /// Moves the last edit to the redo stack without touching any config.
+    pub fn undo_edit(&mut self) -> Option<&Edit> {
+        let edit = self.undo_stack.pop()?;
+        self.redo_stack.push(edit);
+        self.redo_stack.last()
+    }
+
+    /// Moves the last undone edit back to the undo stack without touching any config.
+    pub fn redo_edit(&mut self) -> Option<&Edit> {
+        let edit = self.redo_stack.pop()?;
+        self.undo_stack.push(edit);
+        self.undo_stack.last()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Marks the config as it is now as saved.
+    pub fn mark_saved(&mut self) {
+        self.saved_id = self.current_id();
+        self.changed();
+    }
+
+    /// Whether the config differs from what was last saved, or opened.
+    pub fn is_dirty(&self) -> bool {
+        self.current_id() != self.saved_id
+    }
+
+    /// Marks the config as differing from what was saved, as when the file was replaced under
+    /// it. It stays that way until the next save.
+    pub fn mark_unsaved(&mut self) {
+        self.saved_id = Some(usize::max_value());
+        self.changed();
+    }
+
+    /// Forgets all edits, as when another config is opened.
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.saved_id = None;
+        self.changed();
+    }
+
+    /// The config texts before and after every edit, e.g. to find the families they name.
+    pub fn texts(&self) -> Vec<&str> {
+        self.undo_stack
+            .iter()
+            .chain(&self.redo_stack)
+            .flat_map(|x| vec![x.before.as_str(), x.after.as_str()])
+            .collect()
+    }
+
+    fn push_undo(&mut self, description: String, before: String, after: String) {
+        self.undo_stack.push(Edit {
+            description: description,
+            before: before,
+            after: after,
+            id: self.next_id,
+        });
+        self.next_id += 1;
+    }
+
+    fn current_id(&self) -> Option<usize> {
+        self.undo_stack.last().map(|x| x.id)
+    }
+
+    fn changed(&self) {
+        if let Some(ref on_change) = self.on_change {
+            on_change(self);
+        }
+    }
+}
+
+/// Where the CLI keeps the journal of the config at `config_path`, under
+/// `$XDG_CACHE_HOME/typeholder`.
+pub fn journal_path(config_path: &Path) -> io::Result<PathBuf> {
+    Ok(deserialization::user_cache_dir()?.join("typeholder").join(format!(
+        "{}.journal",
+        deserialization::path_key(config_path)
+    )))
+}
+
+/// Reads the journal at `path`, an empty history when there is none yet.
+pub fn read_journal(path: &Path) -> io::Result<History> {
+    let mut text = String::new();
+    match File::open(path) {
+        Ok(mut f) => f.read_to_string(&mut text)?,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(History::new()),
+        Err(err) => return Err(err),
+    };
+    parse_journal(&text).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Malformed journal {}", path.display()),
+        )
+    })
+}
+
+pub fn write_journal(path: &Path, history: &History) -> io::Result<()> {
+    serialization::write_atomically(path, format_journal(history).as_bytes())
+}
+
+// every edit is a `undo|redo <before length> <after length> <description>` line followed by
+// both texts, undo edits oldest first and redo edits in stack order
+fn format_journal(history: &History) -> String {
+    let mut text = String::from(JOURNAL_HEADER);
+    let stacks = [("undo", &history.undo_stack), ("redo", &history.redo_stack)];
+    for &(kind, stack) in stacks.iter() {
+        for edit in stack {
+            text.push_str(&format!(
+                "{} {} {} {}\n",
+                kind,
+                edit.before.len(),
+                edit.after.len(),
+                edit.description.replace('\n', " ")
+            ));
+            text.push_str(&edit.before);
+            text.push_str(&edit.after);
+        }
+    }
+    text
+}
+
+fn parse_journal(text: &str) -> Option<History> {
+    if !text.starts_with(JOURNAL_HEADER) {
+        return None;
+    }
+    let mut history = History::new();
+    let mut rest = &text[JOURNAL_HEADER.len()..];
+    while !rest.is_empty() {
+        let line_end = rest.find('\n')?;
+        let mut fields = rest[..line_end].splitn(4, ' ');
+        let kind = fields.next()?;
+        let before_len = fields.next()?.parse::<usize>().ok()?;
+        let after_len = fields.next()?.parse::<usize>().ok()?;
+        let description = String::from(fields.next().unwrap_or(""));
+        let body = &rest[line_end + 1..];
+        let before = String::from(body.get(..before_len)?);
+        let after = String::from(body.get(before_len..before_len + after_len)?);
+        history.push_undo(description, before, after);
+        if kind == "redo" {
+            let edit = history.undo_stack.pop()?;
+            history.redo_stack.push(edit);
+        } else if kind != "undo" {
+            return None;
+        }
+        rest = &body[before_len + after_len..];
+    }
+    history.saved_id = history.current_id();
+    Some(history)
+}
+
+
+#[test]
+fn test_history() {
+    let families = vec![RefCell::new(Family::new("DejaVu Sans"))];
+    let placeholders = vec![];
+    let mut config = deserialization::parse(DEFAULT_FONTS_CONF, &families, &placeholders).unwrap();
+    let mut history = History::new();
+
+    history
+        .apply(&mut config, "add alias", |x| x.add_alias("Brand UI"))
+        .unwrap();
+    assert!(
+        history
+            .apply(&mut config, "add alias", |x| x.add_alias("Brand UI"))
+            .is_err()
+    );
+    history
+        .apply(&mut config, "rename alias", |x| {
+            x.rename_alias("Brand UI", "Brand")
+        })
+        .unwrap();
+    assert!(history.is_dirty());
+
+    assert_eq!(history.undo(&mut config).unwrap(), Some(String::from("rename alias")));
+    assert!(config.alias("Brand UI").is_ok());
+    history.mark_saved();
+    assert_eq!(history.undo(&mut config).unwrap(), Some(String::from("add alias")));
+    assert!(config.alias("Brand UI").is_err() && history.is_dirty());
+    assert_eq!(history.undo(&mut config).unwrap(), None);
+    history.redo(&mut config).unwrap();
+    assert!(!history.is_dirty());
+
+    // an edit failing half way is rolled back and not recorded
+    assert!(
+        history
+            .apply(&mut config, "add aliases", |x| {
+                x.add_alias("Brand Mono")?;
+                x.add_alias("Brand UI")
+            })
+            .is_err()
+    );
+    assert!(config.alias("Brand Mono").is_err() && !history.is_dirty());
+
+    // the journal keeps both stacks
+    let journal = parse_journal(&format_journal(&history)).unwrap();
+    assert_eq!(journal.undo_stack.len(), 1);
+    assert_eq!(journal.redo_stack[0].after, history.redo_stack[0].after);
+    assert_eq!(journal.redo_stack[0].description, "rename alias");
+}
@@ -1,41 +1,805 @@
 #![feature(conservative_impl_trait)]
+#![feature(slice_patterns)]
 
 extern crate glib;
 extern crate gtk;
-//extern crate gdk;
+extern crate gdk;
 extern crate pango;
 extern crate itertools;
 extern crate sxd_document;
 
-use std::ops::Deref;
-use std::time::Instant;
+use std::cell::RefCell;
+use std::env;
+use std::error::Error;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
 use itertools::Itertools;
 use glib::{Continue, idle_add, timeout_add, timeout_add_seconds};
 use gtk::prelude::*;
 use gtk::{WidgetExt, Window, WindowPosition, WindowType, HeaderBar, StackSwitcher, ToggleButton,
-          Image, IconSize, Paned, Orientation, TreeStore, TreeView, TreeViewColumn,
-          CellRendererText, ListBox, ListBoxRow, Label, Viewport, ScrolledWindow, PolicyType};
+          Button, Image, IconSize, Paned, Orientation, TreeStore, TreeView, TreeViewColumn,
+          CellRendererText, ListBox, ListBoxRow, Label, Viewport, ScrolledWindow, PolicyType,
+          MessageDialog, DialogFlags, MessageType, ButtonsType, ResponseType, FileChooserDialog,
+          FileChooserAction, TreePath, Dialog, ActionBar, TreeIter, TreeViewDropPosition,
+          TargetEntry, TargetFlags, DestDefaults, SearchBar, SearchEntry, ComboBoxText,
+          CellRendererPixbuf};
 
 #[macro_use]
 extern crate lazy_static;
 
 mod alias;
+mod backup;
+mod charset_editor;
+mod cli;
 mod consts;
 mod range;
 mod family;
 mod config;
+mod error;
 mod deserialization;
+mod scan_match;
+mod serialization;
+mod effective;
+mod foreign;
+mod fontconfig;
+mod history;
+mod search;
+mod simulation;
+mod validation;
 
-fn append_text_column(tree: &TreeView) {
+use alias::{Alias, Binding, FamilyList, FAMILY_LISTS};
+use backup::Backup;
+use config::Config;
+use consts::{DEFAULT_FONTS_CONF, INVALID_CONFIG};
+use effective::EffectiveConfig;
+use error::EditError;
+use family::Family;
+use history::History;
+use search::FontQuery;
+use validation::{Diagnostic, Severity};
+
+/// How many rows of the fonts list are rendered per main loop iteration.
+const PREVIEW_BATCH_SIZE: usize = 64;
+
+// drag and drop target infos
+const TARGET_FONT_FAMILY: u32 = 0;
+const TARGET_ALIAS_ENTRY: u32 = 1;
+
+/// Family colors of the simulation page, reused in order.
+const SIMULATION_COLORS: [&str; 6] = ["#1c71d8", "#26a269", "#e66100", "#9141ac", "#986a44",
+                                      "#2ec27e"];
+const UNRESOLVED_COLOR: &str = "#c01c28";
+
+const RESPONSE_OPEN: i32 = 1;
+const RESPONSE_DEFAULT: i32 = 2;
+
+const DISCARD_QUESTION: &str = "The config has unsaved changes. Discard them?";
+
+fn append_text_column(tree: &TreeView, index: i32) {
     let column = TreeViewColumn::new();
     let cell = CellRendererText::new();
 
     column.pack_start(&cell, true);
-    column.add_attribute(&cell, "text", 0);
+    column.add_attribute(&cell, "text", index);
+    tree.append_column(&column);
+}
+
+fn append_icon_column(tree: &TreeView, index: i32) {
+    let column = TreeViewColumn::new();
+    let cell = CellRendererPixbuf::new();
+
+    column.pack_start(&cell, false);
+    column.add_attribute(&cell, "icon-name", index);
     tree.append_column(&column);
 }
 
+fn icon_button(icon_name: &str, tooltip: &str) -> Button {
+    let button = Button::new();
+    button.set_image(&Image::new_from_icon_name(icon_name, IconSize::Menu.into()));
+    button.set_tooltip_text(Some(tooltip));
+    button
+}
+
+/// Escapes `text` for use in Pango markup, both as content and inside attribute quotes.
+fn escape_markup(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The family name drawn in that family, followed by `sample` if there is one.
+fn preview_markup(family_name: &str, sample: &str) -> String {
+    let family_name = escape_markup(family_name);
+    if sample.is_empty() {
+        format!("<span font_family=\"{0}\">{0}</span>", family_name)
+    } else {
+        format!(
+            "<span font_family=\"{0}\">{0}\n<big>{1}</big></span>",
+            family_name,
+            escape_markup(sample)
+        )
+    }
+}
+
+fn show_error(window: &Window, message: &str) {
+    let dialog = MessageDialog::new(
+        Some(window),
+        DialogFlags::MODAL,
+        MessageType::Error,
+        ButtonsType::Close,
+        message,
+    );
+    dialog.run();
+    dialog.destroy();
+}
+
+/// Asks a yes or no `question`, true if the answer was yes.
+fn confirm(window: &Window, question: &str) -> bool {
+    let dialog = MessageDialog::new(
+        Some(window),
+        DialogFlags::MODAL,
+        MessageType::Question,
+        ButtonsType::YesNo,
+        question,
+    );
+    let response = dialog.run();
+    dialog.destroy();
+    response == ResponseType::Yes.into()
+}
+
+/// The placeholders of the configs opened so far, see `deserialization::placeholders`. Like
+/// the installed families they have to outlive the signal handlers, so each name gets one that
+/// every config naming it shares, rather than a set per opened file.
+#[derive(Default)]
+struct PlaceholderPool {
+    placeholders: RefCell<Vec<&'static RefCell<Family>>>,
+}
+
+impl PlaceholderPool {
+    /// The placeholders for the uninstalled families `text` names.
+    fn placeholders_for(
+        &self,
+        text: &str,
+        families: &Vec<RefCell<Family>>,
+    ) -> Vec<&'static RefCell<Family>> {
+        let mut pool = self.placeholders.borrow_mut();
+        let mut result = vec![];
+        for placeholder in deserialization::placeholders(&[text], families) {
+            let known = pool.iter().cloned().find(
+                |x| x.borrow().name == placeholder.borrow().name,
+            );
+            let placeholder = match known {
+                Some(x) => x,
+                None => {
+                    let x: &'static RefCell<Family> = Box::leak(Box::new(placeholder));
+                    pool.push(x);
+                    x
+                }
+            };
+            result.push(placeholder);
+        }
+        result
+    }
+}
+
+/// Loads the fonts.conf at `config_path`, asking what to do when it is broken.
+fn parse_or_ask(
+    window: &Window,
+    families: &'static Vec<RefCell<Family>>,
+    pool: &PlaceholderPool,
+    config_path: &Path,
+) -> Option<Config<'static>> {
+    let result = deserialization::read_file(config_path)
+        .map_err(Box::<Error>::from)
+        .and_then(|text| {
+            let placeholders = pool.placeholders_for(&text, families);
+            deserialization::parse(&text, families, placeholders).map_err(Box::<Error>::from)
+        });
+    let err = match result {
+        Ok(config) => return Some(config),
+        Err(err) => err,
+    };
+
+    let dialog = MessageDialog::new(
+        Some(window),
+        DialogFlags::MODAL,
+        MessageType::Error,
+        ButtonsType::None,
+        &format!("Failed to load {}\n\n{}", config_path.display(), err),
+    );
+    dialog.add_button("_Open in Editor", RESPONSE_OPEN);
+    dialog.add_button("_Start from Defaults", RESPONSE_DEFAULT);
+    dialog.add_button("_Cancel", ResponseType::Cancel.into());
+    let response = dialog.run();
+    dialog.destroy();
+
+    match response {
+        RESPONSE_OPEN => {
+            // file URIs are absolute and percent-encoded
+            let absolute_path = config_path.canonicalize().unwrap_or_else(
+                |_| config_path.to_path_buf(),
+            );
+            let opened = glib::filename_to_uri(&absolute_path, None)
+                .map_err(|e| e.to_string())
+                .and_then(|uri| {
+                    gtk::show_uri(None, &uri, gtk::get_current_event_time())
+                        .map_err(|e| e.to_string())
+                });
+            if let Err(e) = opened {
+                show_error(window, &format!("Failed to open {}\n\n{}", config_path.display(), e));
+            }
+            None
+        }
+        RESPONSE_DEFAULT => Some(
+            deserialization::parse(DEFAULT_FONTS_CONF, families, vec![]).expect(INVALID_CONFIG),
+        ),
+        _ => None,
+    }
+}
+
+/// Asks for a fonts.conf path, `None` if the user cancelled.
+fn choose_config_path(
+    window: &Window,
+    action: FileChooserAction,
+    current: &Path,
+) -> Option<PathBuf> {
+    let (title, accept) = match action {
+        FileChooserAction::Save => ("Save fonts.conf As", "_Save"),
+        _ => ("Open fonts.conf", "_Open"),
+    };
+    let dialog = FileChooserDialog::new(Some(title), Some(window), action);
+    dialog.add_button("_Cancel", ResponseType::Cancel.into());
+    dialog.add_button(accept, ResponseType::Accept.into());
+    dialog.set_do_overwrite_confirmation(true);
+    dialog.set_filename(current);
+
+    let path = if dialog.run() == ResponseType::Accept.into() {
+        dialog.get_filename()
+    } else {
+        None
+    };
+    dialog.destroy();
+    path
+}
+
+/// Asks which backup to put back, `None` if the user cancelled.
+fn choose_backup<'a>(window: &Window, backups: &'a [Backup]) -> Option<&'a Backup> {
+    let dialog = Dialog::new_with_buttons(
+        Some("Restore Previous Version"),
+        Some(window),
+        DialogFlags::MODAL,
+        &[
+            ("_Cancel", ResponseType::Cancel.into()),
+            ("_Restore", ResponseType::Ok.into()),
+        ],
+    );
+    dialog.set_default_size(320, 360);
+    let list = ListBox::new();
+    for backup in backups {
+        let label = Label::new(Some(&*backup.label()));
+        label.set_xalign(0.0);
+        list.add(&label);
+    }
+    list.select_row(list.get_row_at_index(0).as_ref());
+    {
+        let dialog = dialog.clone();
+        list.connect_row_activated(move |_, _| dialog.response(ResponseType::Ok.into()));
+    }
+    let scrolled = ScrolledWindow::new(None, None);
+    scrolled.set_vexpand(true);
+    scrolled.add(&list);
+    dialog.get_content_area().add(&scrolled);
+    dialog.show_all();
+
+    let index = if dialog.run() == ResponseType::Ok.into() {
+        list.get_selected_row().map(|x| x.get_index() as usize)
+    } else {
+        None
+    };
+    dialog.destroy();
+    index.and_then(|x| backups.get(x))
+}
+
+/// Column 1 of the aliases and charsets stores: the foreign entry index of a row, or
+/// `NOT_FOREIGN` for rows typeholder manages.
+const NOT_FOREIGN: i32 = -1;
+
+fn alias_label(alias: &Alias) -> String {
+    match alias.binding {
+        Binding::Weak => alias.name.clone(),
+        binding => format!("{} ({} binding)", alias.name, binding.name()),
+    }
+}
+
+fn list_label(list: FamilyList) -> &'static str {
+    match list {
+        FamilyList::Prefer => "Prefer",
+        FamilyList::Accept => "Accept",
+        FamilyList::Default => "Default",
+    }
+}
+
+/// Adds an alias row with one group row per language rule and family list under it. Besides the
+/// label and foreign index, every row stores its alias name, list name, entry name and rule
+/// language in columns 2 to 5, left empty where they don't apply.
+fn insert_alias_rows(store: &TreeStore, alias: &Alias, label: &str, foreign_index: i32) {
+    let columns = [0, 1, 2, 3, 4, 5];
+    let iter = store.insert_with_values(
+        None,
+        None,
+        &columns,
+        &[&label, &foreign_index, &alias.name, &"", &"", &""],
+    );
+    for rule in &alias.language_rules {
+        let rule_iter = store.insert_with_values(
+            Some(&iter),
+            None,
+            &columns,
+            &[
+                &format!("Language {}", rule.language),
+                &foreign_index,
+                &alias.name,
+                &"",
+                &"",
+                &rule.language,
+            ],
+        );
+        for family in &rule.families {
+            let family = family.borrow();
+            store.insert_with_values(
+                Some(&rule_iter),
+                None,
+                &columns,
+                &[&family.label(), &foreign_index, &alias.name, &"", &family.name, &rule.language],
+            );
+        }
+    }
+    for &list in FAMILY_LISTS.iter() {
+        if list != FamilyList::Prefer && alias.list(list).is_empty() {
+            continue;
+        }
+        let list_iter = store.insert_with_values(
+            Some(&iter),
+            None,
+            &columns,
+            &[&list_label(list), &foreign_index, &alias.name, &list.name(), &"", &""],
+        );
+        for j in alias.list(list) {
+            let entry_label = match j {
+                &alias::Entry::Family(family) => family.borrow().label(),
+                &alias::Entry::Alias(ref name) => format!("{} (alias)", name),
+            };
+            store.insert_with_values(
+                Some(&list_iter),
+                None,
+                &columns,
+                &[&entry_label, &foreign_index, &alias.name, &list.name(), &j.name(), &""],
+            );
+        }
+    }
+}
+
+/// What a row of the aliases store refers to.
+struct AliasRow {
+    alias: String,
+    list: Option<FamilyList>,
+    entry: Option<String>,
+    language: Option<String>,
+}
+
+/// A family list of an alias, or one of its language rules.
+enum TargetList {
+    List(FamilyList),
+    Language(String),
+}
+
+/// Reads an aliases store row, `None` for rows of foreign aliases.
+fn alias_row<M: TreeModelExt>(model: &M, iter: &TreeIter) -> Option<AliasRow> {
+    if model.get_value(iter, 1).get::<i32>() != Some(NOT_FOREIGN) {
+        return None;
+    }
+    let non_empty = |column| {
+        model.get_value(iter, column).get::<String>().and_then(|x| if x.is_empty() {
+            None
+        } else {
+            Some(x)
+        })
+    };
+    Some(AliasRow {
+        alias: non_empty(2)?,
+        list: non_empty(3).and_then(|x| FamilyList::from_name(&x)),
+        entry: non_empty(4),
+        language: non_empty(5),
+    })
+}
+
+fn selected_alias_row(tree: &TreeView) -> Option<AliasRow> {
+    let (model, iter) = tree.get_selection().get_selected()?;
+    alias_row(&model, &iter)
+}
+
+/// The name of the typeholder alias the selected row belongs to.
+fn selected_alias(tree: &TreeView) -> Option<String> {
+    selected_alias_row(tree).map(|x| x.alias)
+}
+
+/// Where a drop at `x`, `y` lands: the alias, the list or language rule and the slot in it,
+/// `None` to append. Dropping onto an alias row appends to its prefer list.
+fn alias_drop_target(
+    tree: &TreeView,
+    store: &TreeStore,
+    config: &Config,
+    x: i32,
+    y: i32,
+) -> Option<(String, TargetList, Option<usize>)> {
+    let (path, drop_position) = tree.get_dest_row_at_pos(x, y)?;
+    let row = alias_row(store, &store.get_iter(&path?)?)?;
+    let list = match row.language {
+        Some(language) => TargetList::Language(language),
+        None => TargetList::List(row.list.unwrap_or(FamilyList::Prefer)),
+    };
+    let slot = match row.entry {
+        Some(entry) => {
+            let alias = config.alias(&row.alias).ok()?;
+            let index = match list {
+                TargetList::List(list) => alias.position(list, &entry)?,
+                TargetList::Language(ref language) => {
+                    alias.language_rule(language)?.position(&entry)?
+                }
+            };
+            match drop_position {
+                TreeViewDropPosition::After |
+                TreeViewDropPosition::IntoOrAfter => Some(index + 1),
+                _ => Some(index),
+            }
+        }
+        None => None,
+    };
+    Some((row.alias, list, slot))
+}
+
+fn drag_targets() -> Vec<TargetEntry> {
+    vec![
+        TargetEntry::new("typeholder/font-family", TargetFlags::SAME_APP, TARGET_FONT_FAMILY),
+        TargetEntry::new("typeholder/alias-entry", TargetFlags::SAME_WIDGET, TARGET_ALIAS_ENTRY),
+    ]
+}
+
+/// Asks for a single line of text, `None` if the user cancelled or left it empty.
+fn ask_text(window: &Window, title: &str, initial: &str) -> Option<String> {
+    let dialog = Dialog::new_with_buttons(
+        Some(title),
+        Some(window),
+        DialogFlags::MODAL,
+        &[
+            ("_Cancel", ResponseType::Cancel.into()),
+            ("_OK", ResponseType::Ok.into()),
+        ],
+    );
+    dialog.set_default_response(ResponseType::Ok.into());
+    let entry = gtk::Entry::new();
+    entry.set_text(initial);
+    entry.set_activates_default(true);
+    dialog.get_content_area().add(&entry);
+    dialog.show_all();
+
+    let text = if dialog.run() == ResponseType::Ok.into() {
+        entry.get_text().map(|x| String::from(x.trim()))
+    } else {
+        None
+    };
+    dialog.destroy();
+    text.and_then(|x| if x.is_empty() { None } else { Some(x) })
+}
+
+fn fill_aliases_store(tree: &TreeView, store: &TreeStore, config: &Config) {
+    store.clear();
+    for i in &config.aliases {
+        insert_alias_rows(store, i, &alias_label(i), NOT_FOREIGN);
+    }
+    for (index, i) in config.foreign_aliases().iter().enumerate() {
+        insert_alias_rows(
+            store,
+            i,
+            &format!("{} (foreign)", alias_label(i)),
+            index as i32,
+        );
+    }
+    tree.expand_all();
+}
+
+fn range_description(range: &range::Range) -> String {
+    let (range_name, range_type, range_value) = match range {
+        &range::Range::Block {
+            name: ref n,
+            code_points: ref v,
+        } => (n, "Block", format!("0x{:x}..0x{:x}", v.0, v.1)),
+        &range::Range::Script {
+            name: ref n,
+            code_points: ref v,
+        } => (
+            n,
+            "Script",
+            v.iter()
+                .map(|&(x, y)| format!("0x{:x}..0x{:x}", x, y))
+                .join(", "),
+        ),
+        &range::Range::Custom {
+            name: ref n,
+            code_points: ref v,
+        } => (n, "Custom", format!("0x{:x}..0x{:x}", v.0, v.1)),
+    };
+    format!("{}: {} {}", range_name, range_type, range_value)
+}
+
+fn fill_charsets_store(tree: &TreeView, store: &TreeStore, config: &Config) {
+    store.clear();
+    let without_fallback = config.strips_without_fallback();
+    for i in &config.scan_matches {
+        let name = &i.family.borrow().name;
+        let iter = store.insert_with_values(
+            None,
+            None,
+            &[0, 1, 2],
+            &[&i.family.borrow().label(), &NOT_FOREIGN, name],
+        );
+        let redundant = i.redundant_ranges();
+        for j in &i.stripped_ranges {
+            let mut description = range_description(j);
+            if without_fallback.iter().any(|&(x, ref y)| {
+                x as *const _ == i.family as *const _ && y == j
+            })
+            {
+                description.push_str(" (no fallback)");
+            }
+            if redundant.iter().any(|x| *x as *const _ == j as *const _) {
+                description.push_str(" (already stripped by other ranges)");
+            }
+            store.insert_with_values(
+                Some(&iter),
+                None,
+                &[0, 1, 2],
+                &[&description, &NOT_FOREIGN, name],
+            );
+        }
+        for language in &i.stripped_languages {
+            store.insert_with_values(
+                Some(&iter),
+                None,
+                &[0, 1, 2],
+                &[&format!("{}: Language", language), &NOT_FOREIGN, name],
+            );
+        }
+    }
+    for (index, i) in config.foreign_scan_matches().iter().enumerate() {
+        let iter = store.insert_with_values(
+            None,
+            None,
+            &[0, 1],
+            &[&format!("{} (foreign)", i.family.borrow().name), &(index as i32)],
+        );
+        for j in &i.ranges {
+            store.insert_with_values(
+                Some(&iter),
+                None,
+                &[0, 1],
+                &[&range_description(j), &NOT_FOREIGN],
+            );
+        }
+    }
+    tree.expand_all();
+}
+
+/// The family of the selected typeholder row of the charsets store, kept in column 2.
+fn selected_charset_family(tree: &TreeView) -> Option<String> {
+    let (model, iter) = tree.get_selection().get_selected()?;
+    model.get_value(&iter, 2).get::<String>()
+}
+
+/// Offers to adopt the foreign entry at `path` into typeholder management.
+fn ask_adopt<F>(window: &Window, store: &TreeStore, path: &TreePath, adopt: F) -> bool
+where
+    F: FnOnce(usize) -> Result<(), EditError>,
+{
+    let index = match store.get_iter(path).and_then(
+        |x| store.get_value(&x, 1).get::<i32>(),
+    ) {
+        Some(index) if index != NOT_FOREIGN => index as usize,
+        _ => return false,
+    };
+
+    if !confirm(
+        window,
+        "This entry was written by hand. Let typeholder manage it from now on?",
+    )
+    {
+        return false;
+    }
+
+    match adopt(index) {
+        Ok(()) => true,
+        Err(err) => {
+            show_error(window, &format!("{}", err));
+            false
+        }
+    }
+}
+
+/// Fills the read-only merged view, one row per alias and per scan match with the file it
+/// came from.
+fn fill_effective_store(tree: &TreeView, store: &TreeStore, effective: &EffectiveConfig) {
+    store.clear();
+    for alias in &effective.aliases {
+        let iter = store.insert_with_values(None, None, &[0, 1], &[&alias.name, &""]);
+        for &list in FAMILY_LISTS.iter() {
+            if alias.list(list).is_empty() {
+                continue;
+            }
+            let list_iter =
+                store.insert_with_values(Some(&iter), None, &[0, 1], &[&list.name(), &""]);
+            for family in alias.list(list) {
+                store.insert_with_values(
+                    Some(&list_iter),
+                    None,
+                    &[0, 1],
+                    &[
+                        &effective::family_label(&family.value),
+                        &family.source.to_string_lossy().into_owned(),
+                    ],
+                );
+            }
+        }
+    }
+    if !effective.scan_matches.is_empty() {
+        let iter = store.insert_with_values(None, None, &[0, 1], &[&"Scan matches", &""]);
+        for family in &effective.scan_matches {
+            store.insert_with_values(
+                Some(&iter),
+                None,
+                &[0, 1],
+                &[&family.value, &family.source.to_string_lossy().into_owned()],
+            );
+        }
+    }
+    for problem in &effective.problems {
+        store.insert_with_values(
+            None,
+            None,
+            &[0, 1],
+            &[&problem.value, &problem.source.to_string_lossy().into_owned()],
+        );
+    }
+    tree.expand_all();
+}
+
+fn fill_problems_store(store: &TreeStore, summary: &Label, diagnostics: &[Diagnostic]) {
+    store.clear();
+    for diagnostic in diagnostics {
+        let icon_name = match diagnostic.severity {
+            Severity::Error => "dialog-error-symbolic",
+            Severity::Warning => "dialog-warning-symbolic",
+        };
+        store.insert_with_values(None, None, &[0, 1], &[&icon_name, &diagnostic.message]);
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|x| x.severity == Severity::Error)
+        .count();
+    summary.set_text(&match (errors, diagnostics.len() - errors) {
+        (0, 0) => String::from("No problems found."),
+        (errors, warnings) => format!("Errors: {}, warnings: {}", errors, warnings),
+    });
+}
+
+/// Refills `combo` with the typeholder aliases, keeping the active one if it still exists.
+fn fill_alias_combo(combo: &ComboBoxText, config: &Config) {
+    let active = combo.get_active_text();
+    combo.remove_all();
+    for (index, alias) in config.aliases.iter().enumerate() {
+        combo.append_text(&alias.name);
+        if active.as_ref() == Some(&alias.name) {
+            combo.set_active(index as i32);
+        }
+    }
+    if combo.get_active() < 0 && !config.aliases.is_empty() {
+        combo.set_active(0);
+    }
+}
+
+/// Markup for the simulated sample, each run drawn in and colored after its family, and for
+/// a legend of those colors.
+fn simulation_markup(resolved: &[simulation::Resolved]) -> (String, String) {
+    let mut runs: Vec<(Option<&RefCell<Family>>, String)> = vec![];
+    for i in resolved {
+        let same_family = runs.last().map_or(false, |&(family, _)| {
+            family.map(|x| x as *const _) == i.family.map(|x| x as *const _)
+        });
+        if same_family {
+            runs.last_mut().unwrap().1.push(i.character);
+        } else {
+            runs.push((i.family, i.character.to_string()));
+        }
+    }
+
+    let mut legend: Vec<&RefCell<Family>> = vec![];
+    let mut sample = String::new();
+    for (family, text) in runs {
+        let text = escape_markup(&text);
+        let family = match family {
+            Some(family) => family,
+            None => {
+                sample.push_str(&format!(
+                    "<span background=\"{}\" foreground=\"white\">{}</span>",
+                    UNRESOLVED_COLOR,
+                    text
+                ));
+                continue;
+            }
+        };
+        let index = match legend.iter().position(|x| *x as *const _ == family as *const _) {
+            Some(index) => index,
+            None => {
+                legend.push(family);
+                legend.len() - 1
+            }
+        };
+        sample.push_str(&format!(
+            "<span font_family=\"{}\" foreground=\"{}\">{}</span>",
+            escape_markup(&family.borrow().name),
+            SIMULATION_COLORS[index % SIMULATION_COLORS.len()],
+            text
+        ));
+    }
+
+    let mut legend = legend
+        .iter()
+        .enumerate()
+        .map(|(index, family)| {
+            format!(
+                "<span foreground=\"{}\">\u{25a0}</span> {}",
+                SIMULATION_COLORS[index % SIMULATION_COLORS.len()],
+                escape_markup(&family.borrow().name)
+            )
+        })
+        .collect_vec();
+    // what the alias misses is easier to fix knowing which script and block it is in
+    let unresolved = resolved
+        .iter()
+        .filter(|x| x.family.is_none())
+        .map(|x| range::code_point_context(x.character as u32))
+        .unique()
+        .collect_vec();
+    if !unresolved.is_empty() {
+        legend.push(format!(
+            "<span foreground=\"{}\">\u{25a0}</span> No family of the alias: {}",
+            UNRESOLVED_COLOR,
+            escape_markup(&unresolved.join("; "))
+        ));
+    }
+    (sample, legend.join("\n"))
+}
+
 fn main() {
+    let args = env::args().skip(1).collect_vec();
+    let (config_path, command) = match cli::parse_options(&args) {
+        Ok(options) => options,
+        Err(err) => process::exit(cli::report(err)),
+    };
+    if !command.is_empty() {
+        process::exit(cli::run(&config_path, &command));
+    }
+
     if gtk::init().is_err() {
         println!("Failed to initialize GTK.");
         return;
@@ -46,26 +810,59 @@ fn main() {
     window.set_title("Typeholder");
     window.set_position(WindowPosition::Center);
     window.set_default_size(640, 480);
-    window.connect_delete_event(|_, _| {
-        gtk::main_quit();
-        Inhibit(false)
-    });
 
-    // debug
-    println!("UNICODE_BLOCKS[15]: {:?}", range::UNICODE_BLOCKS[15]);
-    println!("UNICODE_SCRIPTS[35]: {:?}", range::UNICODE_SCRIPTS[35]);
+    // signal handlers are 'static, so the families they borrow live as long as the process
+    let available_families: &'static Vec<RefCell<Family>> =
+        Box::leak(Box::new(deserialization::list_families(
+            &window.create_pango_context().expect(
+                "Failed to create Pango context!",
+            ),
+        )));
 
-    let available_families = deserialization::list_families(&window.create_pango_context().expect(
-        "Failed to create Pango context!",
-    ));
-
-    let fc_config = deserialization::parse_or_default(&available_families);
-    println!("Parsed config: {:?}", fc_config);
+    let placeholder_pool = Rc::new(PlaceholderPool::default());
+    let fc_config = match parse_or_ask(
+        &window,
+        available_families,
+        &placeholder_pool,
+        &config_path,
+    ) {
+        Some(config) => Rc::new(RefCell::new(config)),
+        None => return,
+    };
+    let config_path = Rc::new(RefCell::new(config_path));
 
 
 
     let header_bar = HeaderBar::new();
+    header_bar.set_title("Typeholder");
     header_bar.set_show_close_button(true);
+    header_bar.set_subtitle(Some(&*config_path.borrow().to_string_lossy()));
+
+    // every edit goes through the history so that it can be undone
+    let history = Rc::new(RefCell::new(History::new()));
+    {
+        let window = window.clone();
+        let header_bar = header_bar.clone();
+        history.borrow_mut().connect_changed(move |history| {
+            let title = if history.is_dirty() {
+                "*Typeholder"
+            } else {
+                "Typeholder"
+            };
+            window.set_title(title);
+            header_bar.set_title(title);
+        });
+    }
+    {
+        let history = history.clone();
+        window.connect_delete_event(move |window, _| {
+            if history.borrow().is_dirty() && !confirm(window, DISCARD_QUESTION) {
+                return Inhibit(true);
+            }
+            gtk::main_quit();
+            Inhibit(false)
+        });
+    }
 
     let paned = Paned::new(Orientation::Horizontal);
 
@@ -74,104 +871,901 @@ fn main() {
     let stack = gtk::Stack::new();
 
     let aliases_tree = TreeView::new();
-    let aliases_store = TreeStore::new(&[String::static_type()]);
+    let aliases_store = TreeStore::new(
+        &[
+            String::static_type(),
+            i32::static_type(),
+            String::static_type(),
+            String::static_type(),
+            String::static_type(),
+            String::static_type(),
+        ],
+    );
     aliases_tree.set_model(Some(&aliases_store));
     aliases_tree.set_headers_visible(false);
-    append_text_column(&aliases_tree);
-    for i in &fc_config.aliases {
-        let iter = aliases_store.insert_with_values(None, None, &[0], &[&format!("{}", i.name)]);
-        for j in &i.prefer_list {
-            aliases_store.insert_with_values(Some(&iter), None, &[0], &[&j.borrow().name]);
-        }
-    }
-    aliases_tree.expand_all();
+    append_text_column(&aliases_tree, 0);
+    fill_aliases_store(&aliases_tree, &aliases_store, &fc_config.borrow());
 
     let charsets_tree = TreeView::new();
-    let charsets_store = TreeStore::new(&[String::static_type()]);
+    let charsets_store = TreeStore::new(
+        &[String::static_type(), i32::static_type(), String::static_type()],
+    );
     charsets_tree.set_model(Some(&charsets_store));
     charsets_tree.set_headers_visible(false);
-    append_text_column(&charsets_tree);
-    for i in &fc_config.scan_matches {
-        let iter =
-            charsets_store.insert_with_values(None, None, &[0], &[&format!("{}", i.borrow().name)]);
-        for j in &i.borrow().stripped_ranges {
-            let (range_name, range_type, range_value) = match j {
-                &range::Range::Block {
-                    name: ref n,
-                    code_points: ref v,
-                } => (n, "Block", format!("0x{:x}..0x{:x}", v.0, v.1)),
-                &range::Range::Script {
-                    name: ref n,
-                    code_points: ref v,
-                } => (
-                    n,
-                    "Script",
-                    v.iter()
-                        .map(|&(x, y)| format!("0x{:x}..0x{:x}", x, y))
-                        .join(", "),
-                ),
-                &range::Range::Custom {
-                    name: ref n,
-                    code_points: ref v,
-                } => (n, "Custom", format!("0x{:x}..0x{:x}", v.0, v.1)),
-            };
-            charsets_store.insert_with_values(
-                Some(&iter),
-                None,
-                &[0],
-                &[&format!("{}: {} {}", range_name, range_type, range_value)],
-            );
+    append_text_column(&charsets_tree, 0);
+    fill_charsets_store(&charsets_tree, &charsets_store, &fc_config.borrow());
+
+    {
+        let window = window.clone();
+        let aliases_store = aliases_store.clone();
+        let fc_config = fc_config.clone();
+        let history = history.clone();
+        aliases_tree.connect_row_activated(move |tree, path, _| {
+            if ask_adopt(&window, &aliases_store, path, |i| {
+                history.borrow_mut().apply(
+                    &mut fc_config.borrow_mut(),
+                    "Adopt alias",
+                    |x| x.adopt_alias(i),
+                )
+            })
+            {
+                fill_aliases_store(tree, &aliases_store, &fc_config.borrow());
+            }
+        });
+    }
+    {
+        let window = window.clone();
+        let charsets_store = charsets_store.clone();
+        let fc_config = fc_config.clone();
+        let history = history.clone();
+        charsets_tree.connect_row_activated(move |tree, path, _| {
+            if ask_adopt(&window, &charsets_store, path, |i| {
+                history.borrow_mut().apply(
+                    &mut fc_config.borrow_mut(),
+                    "Adopt scan match",
+                    |x| x.adopt_scan_match(i),
+                )
+            })
+            {
+                fill_charsets_store(tree, &charsets_store, &fc_config.borrow());
+            }
+        });
+    }
+
+    let effective_tree = TreeView::new();
+    let effective_store = TreeStore::new(&[String::static_type(), String::static_type()]);
+    effective_tree.set_model(Some(&effective_store));
+    effective_tree.set_headers_visible(false);
+    append_text_column(&effective_tree, 0);
+    append_text_column(&effective_tree, 1);
+    fill_effective_store(&effective_tree, &effective_store, &effective::resolve_system());
+    let effective_scrolled = ScrolledWindow::new(None, None);
+    effective_scrolled.add(&effective_tree);
+
+    aliases_tree.drag_source_set(
+        gdk::ModifierType::BUTTON1_MASK,
+        &drag_targets()[TARGET_ALIAS_ENTRY as usize..],
+        gdk::DragAction::MOVE,
+    );
+    aliases_tree.drag_dest_set(
+        DestDefaults::ALL,
+        &drag_targets(),
+        gdk::DragAction::COPY | gdk::DragAction::MOVE,
+    );
+    aliases_tree.connect_drag_data_get(|tree, _, data, _, _| {
+        if let Some(AliasRow {
+                        alias,
+                        list: Some(list),
+                        entry: Some(entry),
+                        ..
+                    }) = selected_alias_row(tree)
+        {
+            data.set_text(&format!("{}\n{}\n{}", alias, list.name(), entry), -1);
         }
+    });
+    {
+        let window = window.clone();
+        let aliases_store = aliases_store.clone();
+        let fc_config = fc_config.clone();
+        let history = history.clone();
+        aliases_tree.connect_drag_data_received(move |tree, _, x, y, data, info, _| {
+            let text = match data.get_text() {
+                Some(text) => text,
+                None => return,
+            };
+            let target = alias_drop_target(tree, &aliases_store, &fc_config.borrow(), x, y);
+            let (alias, list, slot) = match target {
+                Some(target) => target,
+                None => return,
+            };
+            // show_error runs a main loop, so both borrows end before it does
+            let result = {
+                let mut history = history.borrow_mut();
+                let config = &mut fc_config.borrow_mut();
+                let description = format!("Add {} to {}", text, alias);
+                match list {
+                    TargetList::List(list) if info == TARGET_FONT_FAMILY => {
+                        history.apply(config, &description, |x| {
+                            x.insert_family(&alias, list, &text, slot)
+                        })
+                    }
+                    TargetList::List(list) => {
+                        match text.split('\n').collect_vec().as_slice() {
+                            &[from_alias, from_list, entry] => {
+                                let from_list = FamilyList::from_name(from_list).unwrap();
+                                let description = format!("Move {} to {}", entry, alias);
+                                history.apply(config, &description, |x| {
+                                    x.transfer_family(
+                                        (from_alias, from_list),
+                                        (&alias, list),
+                                        entry,
+                                        slot,
+                                    )
+                                })
+                            }
+                            _ => return,
+                        }
+                    }
+                    // language rules take installed families only, not alias entries
+                    TargetList::Language(ref language) if info == TARGET_FONT_FAMILY => {
+                        history.apply(config, &description, |x| {
+                            x.insert_language_family(&alias, language, &text, slot)
+                        })
+                    }
+                    TargetList::Language(_) => return,
+                }
+            };
+            match result {
+                Ok(()) => fill_aliases_store(tree, &aliases_store, &fc_config.borrow()),
+                Err(err) => show_error(&window, &err.to_string()),
+            }
+        });
+    }
+    {
+        let window = window.clone();
+        let aliases_store = aliases_store.clone();
+        let fc_config = fc_config.clone();
+        let history = history.clone();
+        aliases_tree.connect_key_press_event(move |tree, event| {
+            if event.get_keyval() != gdk::enums::key::Delete {
+                return Inhibit(false);
+            }
+            let row = match selected_alias_row(tree) {
+                Some(row) => row,
+                None => return Inhibit(false),
+            };
+            if let AliasRow {
+                ref alias,
+                list: None,
+                entry: None,
+                language: None,
+            } = row
+            {
+                if !confirm(&window, &format!("Delete the alias {}?", alias)) {
+                    return Inhibit(true);
+                }
+            }
+            let result = {
+                let mut history = history.borrow_mut();
+                let config = &mut fc_config.borrow_mut();
+                match row {
+                    AliasRow {
+                        alias,
+                        language: Some(language),
+                        entry: Some(entry),
+                        ..
+                    } => {
+                        let description = format!("Remove {} from {}", entry, alias);
+                        history.apply(config, &description, |x| {
+                            x.remove_language_family(&alias, &language, &entry)
+                        })
+                    }
+                    AliasRow {
+                        alias,
+                        language: Some(language),
+                        ..
+                    } => {
+                        let description = format!("Remove {} rule from {}", language, alias);
+                        history
+                            .apply(config, &description, |x| {
+                                x.remove_language_rule(&alias, &language)
+                            })
+                            .map(|_| ())
+                    }
+                    AliasRow {
+                        alias,
+                        list: Some(list),
+                        entry: Some(entry),
+                        ..
+                    } => {
+                        let description = format!("Remove {} from {}", entry, alias);
+                        history.apply(config, &description, |x| {
+                            x.remove_family(&alias, list, &entry)
+                        })
+                    }
+                    AliasRow {
+                        alias,
+                        list: None,
+                        entry: None,
+                        language: None,
+                    } => {
+                        let description = format!("Delete {}", alias);
+                        history
+                            .apply(config, &description, |x| x.remove_alias(&alias))
+                            .map(|_| ())
+                    }
+                    // the header of a family list
+                    _ => return Inhibit(false),
+                }
+            };
+            match result {
+                Ok(()) => fill_aliases_store(tree, &aliases_store, &fc_config.borrow()),
+                Err(err) => show_error(&window, &err.to_string()),
+            }
+            Inhibit(true)
+        });
+    }
+
+    let add_alias_button = icon_button("list-add-symbolic", "Add Alias");
+    {
+        let window = window.clone();
+        let aliases_tree = aliases_tree.clone();
+        let aliases_store = aliases_store.clone();
+        let fc_config = fc_config.clone();
+        let history = history.clone();
+        add_alias_button.connect_clicked(move |_| {
+            let name = match ask_text(&window, "Add Alias", "") {
+                Some(name) => name,
+                None => return,
+            };
+            let description = format!("Add {}", name);
+            let result = history.borrow_mut().apply(
+                &mut fc_config.borrow_mut(),
+                &description,
+                |x| x.add_alias(&name),
+            );
+            match result {
+                Ok(()) => fill_aliases_store(&aliases_tree, &aliases_store, &fc_config.borrow()),
+                Err(err) => show_error(&window, &err.to_string()),
+            }
+        });
+    }
+
+    let rename_alias_button = icon_button("document-edit-symbolic", "Rename Alias");
+    {
+        let window = window.clone();
+        let aliases_tree = aliases_tree.clone();
+        let aliases_store = aliases_store.clone();
+        let fc_config = fc_config.clone();
+        let history = history.clone();
+        rename_alias_button.connect_clicked(move |_| {
+            let name = match selected_alias(&aliases_tree) {
+                Some(name) => name,
+                None => return,
+            };
+            let new_name = match ask_text(&window, "Rename Alias", &name) {
+                Some(new_name) => new_name,
+                None => return,
+            };
+            let description = format!("Rename {} to {}", name, new_name);
+            let result = history.borrow_mut().apply(
+                &mut fc_config.borrow_mut(),
+                &description,
+                |x| x.rename_alias(&name, &new_name),
+            );
+            match result {
+                Ok(()) => fill_aliases_store(&aliases_tree, &aliases_store, &fc_config.borrow()),
+                Err(err) => show_error(&window, &err.to_string()),
+            }
+        });
+    }
+
+    let remove_alias_button = icon_button("list-remove-symbolic", "Delete Alias");
+    {
+        let window = window.clone();
+        let aliases_tree = aliases_tree.clone();
+        let aliases_store = aliases_store.clone();
+        let fc_config = fc_config.clone();
+        let history = history.clone();
+        remove_alias_button.connect_clicked(move |_| {
+            let name = match selected_alias(&aliases_tree) {
+                Some(name) => name,
+                None => return,
+            };
+            if !confirm(&window, &format!("Delete the alias {}?", name)) {
+                return;
+            }
+            let description = format!("Delete {}", name);
+            let result = history.borrow_mut().apply(
+                &mut fc_config.borrow_mut(),
+                &description,
+                |x| x.remove_alias(&name),
+            );
+            match result {
+                Ok(_) => fill_aliases_store(&aliases_tree, &aliases_store, &fc_config.borrow()),
+                Err(err) => show_error(&window, &err.to_string()),
+            }
+        });
+    }
+
+    let add_rule_button = icon_button("preferences-desktop-locale-symbolic", "Add Language Rule");
+    {
+        let window = window.clone();
+        let aliases_tree = aliases_tree.clone();
+        let aliases_store = aliases_store.clone();
+        let fc_config = fc_config.clone();
+        let history = history.clone();
+        add_rule_button.connect_clicked(move |_| {
+            let name = match selected_alias(&aliases_tree) {
+                Some(name) => name,
+                None => return,
+            };
+            let language = match ask_text(&window, "Language, e.g. ja or zh-tw", "") {
+                Some(language) => language,
+                None => return,
+            };
+            let description = format!("Add {} rule to {}", language, name);
+            let result = history.borrow_mut().apply(
+                &mut fc_config.borrow_mut(),
+                &description,
+                |x| x.add_language_rule(&name, &language),
+            );
+            match result {
+                Ok(()) => fill_aliases_store(&aliases_tree, &aliases_store, &fc_config.borrow()),
+                Err(err) => show_error(&window, &err.to_string()),
+            }
+        });
+    }
+
+    let aliases_action_bar = ActionBar::new();
+    aliases_action_bar.pack_start(&add_alias_button);
+    aliases_action_bar.pack_start(&rename_alias_button);
+    aliases_action_bar.pack_start(&remove_alias_button);
+    aliases_action_bar.pack_start(&add_rule_button);
+    let aliases_scrolled = ScrolledWindow::new(None, None);
+    aliases_scrolled.set_vexpand(true);
+    aliases_scrolled.add(&aliases_tree);
+    let aliases_page = gtk::Box::new(Orientation::Vertical, 0);
+    aliases_page.add(&aliases_scrolled);
+    aliases_page.add(&aliases_action_bar);
+
+    stack.add_titled(&aliases_page, "aliases", "Aliases");
+    let strip_button = icon_button("document-edit-symbolic", "Strip Ranges");
+    {
+        let window = window.clone();
+        let charsets_tree = charsets_tree.clone();
+        let charsets_store = charsets_store.clone();
+        let fc_config = fc_config.clone();
+        let history = history.clone();
+        strip_button.connect_clicked(move |_| {
+            if let Some(family_name) = selected_charset_family(&charsets_tree) {
+                charset_editor::run(&window, &fc_config, &history, &family_name);
+                fill_charsets_store(&charsets_tree, &charsets_store, &fc_config.borrow());
+            }
+        });
+    }
+
+    let charsets_action_bar = ActionBar::new();
+    charsets_action_bar.pack_start(&strip_button);
+    let charsets_scrolled = ScrolledWindow::new(None, None);
+    charsets_scrolled.set_vexpand(true);
+    charsets_scrolled.add(&charsets_tree);
+    let charsets_page = gtk::Box::new(Orientation::Vertical, 0);
+    charsets_page.add(&charsets_scrolled);
+    charsets_page.add(&charsets_action_bar);
+
+    stack.add_titled(&charsets_page, "charsets", "Charsets");
+    stack.add_titled(&effective_scrolled, "effective", "Effective");
+
+    let simulation_alias = ComboBoxText::new();
+    let simulation_entry = gtk::Entry::new();
+    simulation_entry.set_text("Typeholder \u{5b57}\u{4f53} \u{3042} \u{d55c} \u{1f600}");
+    let simulation_language = gtk::Entry::new();
+    simulation_language.set_placeholder_text(Some("Language, e.g. ja or zh-tw"));
+    let simulation_label = Label::new(None);
+    simulation_label.set_line_wrap(true);
+    simulation_label.set_selectable(true);
+    simulation_label.set_xalign(0.0);
+    let simulation_legend = Label::new(None);
+    simulation_legend.set_xalign(0.0);
+    let comparison_label = Label::new(None);
+    comparison_label.set_xalign(0.0);
+    comparison_label.set_selectable(true);
+    let refresh_simulation = {
+        let comparison_label = comparison_label.clone();
+        let simulation_alias = simulation_alias.clone();
+        let simulation_entry = simulation_entry.clone();
+        let simulation_language = simulation_language.clone();
+        let simulation_label = simulation_label.clone();
+        let simulation_legend = simulation_legend.clone();
+        let fc_config = fc_config.clone();
+        Rc::new(move || {
+            let alias = simulation_alias.get_active_text().unwrap_or_default();
+            let sample = simulation_entry.get_text().unwrap_or_default();
+            let language = simulation_language.get_text().unwrap_or_default();
+            let language = match language.trim() {
+                "" => None,
+                language => Some(language),
+            };
+            // a comparison is only good for the sample it was run on
+            comparison_label.set_text("");
+            match simulation::simulate(&fc_config.borrow(), &alias, &sample, language) {
+                Ok(resolved) => {
+                    let (sample, legend) = simulation_markup(&resolved);
+                    simulation_label.set_markup(&format!("<big>{}</big>", sample));
+                    simulation_legend.set_markup(&legend);
+                }
+                Err(_) => {
+                    simulation_label.set_text("");
+                    simulation_legend.set_text("");
+                }
+            }
+        })
+    };
+
+    let compare_button = Button::new_with_label("Compare with fontconfig");
+    {
+        let window = window.clone();
+        let simulation_alias = simulation_alias.clone();
+        let simulation_entry = simulation_entry.clone();
+        let simulation_language = simulation_language.clone();
+        let comparison_label = comparison_label.clone();
+        let fc_config = fc_config.clone();
+        let config_path = config_path.clone();
+        compare_button.connect_clicked(move |_| {
+            let alias = simulation_alias.get_active_text().unwrap_or_default();
+            let sample = simulation_entry.get_text().unwrap_or_default();
+            let language = simulation_language.get_text().unwrap_or_default();
+            let language = match language.trim() {
+                "" => None,
+                language => Some(language),
+            };
+            // the error dialog runs a main loop in which edits borrow the config again
+            let result = fontconfig::compare(
+                &fc_config.borrow(),
+                &config_path.borrow(),
+                &alias,
+                &sample,
+                language,
+            );
+            let differences = match result {
+                Ok(differences) => differences,
+                Err(err) => return show_error(&window, &err.to_string()),
+            };
+            if differences.is_empty() {
+                comparison_label.set_text("fontconfig agrees with the simulation.");
+                return;
+            }
+            let lines = differences
+                .iter()
+                .map(|x| {
+                    format!(
+                        "{}  simulated {}, fontconfig uses {}",
+                        x.character,
+                        x.simulated.map_or(String::from("nothing"), |y| y.borrow().name.clone()),
+                        x.actual.clone().unwrap_or_else(|| String::from("nothing"))
+                    )
+                })
+                .join("\n");
+            comparison_label.set_text(&lines);
+        });
+    }
+    {
+        let refresh_simulation = refresh_simulation.clone();
+        simulation_alias.connect_changed(move |_| refresh_simulation());
+    }
+    {
+        let refresh_simulation = refresh_simulation.clone();
+        simulation_entry.connect_changed(move |_| refresh_simulation());
+    }
+    {
+        let refresh_simulation = refresh_simulation.clone();
+        simulation_language.connect_changed(move |_| refresh_simulation());
+    }
+
+    let simulation_page = gtk::Box::new(Orientation::Vertical, 12);
+    simulation_page.set_border_width(12);
+    simulation_page.add(&simulation_alias);
+    simulation_page.add(&simulation_entry);
+    simulation_page.add(&simulation_language);
+    simulation_page.add(&simulation_label);
+    simulation_page.add(&simulation_legend);
+    simulation_page.add(&compare_button);
+    simulation_page.add(&comparison_label);
+    // edits happen on the other pages, so simulate again whenever this one is shown
+    {
+        let simulation_alias = simulation_alias.clone();
+        let fc_config = fc_config.clone();
+        simulation_page.connect_map(move |_| {
+            fill_alias_combo(&simulation_alias, &fc_config.borrow());
+            refresh_simulation();
+        });
     }
-    charsets_tree.expand_all();
+    stack.add_titled(&simulation_page, "simulation", "Simulate");
 
-    stack.add_titled(&aliases_tree, "aliases", "Aliases");
-    stack.add_titled(&charsets_tree, "charsets", "Charsets");
+    let problems_summary = Label::new(None);
+    problems_summary.set_xalign(0.0);
+    let problems_tree = TreeView::new();
+    let problems_store = TreeStore::new(&[String::static_type(), String::static_type()]);
+    problems_tree.set_model(Some(&problems_store));
+    problems_tree.set_headers_visible(false);
+    append_icon_column(&problems_tree, 0);
+    append_text_column(&problems_tree, 1);
+    let problems_scrolled = ScrolledWindow::new(None, None);
+    problems_scrolled.set_vexpand(true);
+    problems_scrolled.add(&problems_tree);
+    let problems_page = gtk::Box::new(Orientation::Vertical, 6);
+    problems_page.set_border_width(6);
+    problems_page.add(&problems_summary);
+    problems_page.add(&problems_scrolled);
+    // like the simulation, validate again whenever the page is shown
+    {
+        let fc_config = fc_config.clone();
+        problems_page.connect_map(move |_| {
+            let diagnostics = validation::validate_config(&fc_config.borrow());
+            fill_problems_store(&problems_store, &problems_summary, &diagnostics);
+        });
+    }
+    stack.add_titled(&problems_page, "problems", "Problems");
 
     switcher.set_stack(&stack);
 
+    let open_button = icon_button("document-open-symbolic", "Open");
+    {
+        let window = window.clone();
+        let header_bar = header_bar.clone();
+        let aliases_tree = aliases_tree.clone();
+        let aliases_store = aliases_store.clone();
+        let charsets_tree = charsets_tree.clone();
+        let charsets_store = charsets_store.clone();
+        let fc_config = fc_config.clone();
+        let history = history.clone();
+        let config_path = config_path.clone();
+        let placeholder_pool = placeholder_pool.clone();
+        open_button.connect_clicked(move |_| {
+            if history.borrow().is_dirty() && !confirm(&window, DISCARD_QUESTION) {
+                return;
+            }
+            let current = config_path.borrow().clone();
+            let path = match choose_config_path(&window, FileChooserAction::Open, &current) {
+                Some(path) => path,
+                None => return,
+            };
+            let config = parse_or_ask(&window, available_families, &placeholder_pool, &path);
+            if let Some(config) = config {
+                *fc_config.borrow_mut() = config;
+                history.borrow_mut().clear();
+                fill_aliases_store(&aliases_tree, &aliases_store, &fc_config.borrow());
+                fill_charsets_store(&charsets_tree, &charsets_store, &fc_config.borrow());
+                header_bar.set_subtitle(Some(&*path.to_string_lossy()));
+                *config_path.borrow_mut() = path;
+            }
+        });
+    }
+
+    let save_button = icon_button("document-save-symbolic", "Save");
+    {
+        let window = window.clone();
+        let effective_tree = effective_tree.clone();
+        let effective_store = effective_store.clone();
+        let fc_config = fc_config.clone();
+        let history = history.clone();
+        let config_path = config_path.clone();
+        save_button.connect_clicked(move |_| {
+            // the error dialog runs a main loop in which edits borrow the config again
+            let path = config_path.borrow().clone();
+            let result = serialization::save(&fc_config.borrow(), &path);
+            match result {
+                Ok(()) => {
+                    history.borrow_mut().mark_saved();
+                    fill_effective_store(
+                        &effective_tree,
+                        &effective_store,
+                        &effective::resolve_system(),
+                    )
+                }
+                Err(err) => {
+                    show_error(&window, &format!("Failed to save {}\n\n{}", path.display(), err))
+                }
+            }
+        });
+    }
+
+    let save_as_button = icon_button("document-save-as-symbolic", "Save As");
+    {
+        let window = window.clone();
+        let header_bar = header_bar.clone();
+        let fc_config = fc_config.clone();
+        let history = history.clone();
+        let config_path = config_path.clone();
+        save_as_button.connect_clicked(move |_| {
+            let current = config_path.borrow().clone();
+            let path = match choose_config_path(&window, FileChooserAction::Save, &current) {
+                Some(path) => path,
+                None => return,
+            };
+            let result = serialization::save(&fc_config.borrow(), &path);
+            match result {
+                Ok(()) => {
+                    history.borrow_mut().mark_saved();
+                    header_bar.set_subtitle(Some(&*path.to_string_lossy()));
+                    *config_path.borrow_mut() = path;
+                }
+                Err(err) => {
+                    show_error(&window, &format!("Failed to save {}\n\n{}", path.display(), err))
+                }
+            }
+        });
+    }
+
+    // every save backs up the file it replaces, see `backup`
+    let restore_button = icon_button("document-revert-symbolic", "Restore Previous Version");
+    {
+        let window = window.clone();
+        let aliases_tree = aliases_tree.clone();
+        let aliases_store = aliases_store.clone();
+        let charsets_tree = charsets_tree.clone();
+        let charsets_store = charsets_store.clone();
+        let effective_tree = effective_tree.clone();
+        let effective_store = effective_store.clone();
+        let fc_config = fc_config.clone();
+        let history = history.clone();
+        let config_path = config_path.clone();
+        let placeholder_pool = placeholder_pool.clone();
+        restore_button.connect_clicked(move |_| {
+            let path = config_path.borrow().clone();
+            let backups = match backup::list_backups(&path) {
+                Ok(ref backups) if backups.is_empty() => {
+                    let message = format!("{} has not been backed up yet", path.display());
+                    return show_error(&window, &message);
+                }
+                Ok(backups) => backups,
+                Err(err) => {
+                    let message =
+                        format!("Failed to list backups of {}\n\n{}", path.display(), err);
+                    return show_error(&window, &message);
+                }
+            };
+            let backup = match choose_backup(&window, &backups) {
+                Some(backup) => backup,
+                None => return,
+            };
+            if history.borrow().is_dirty() && !confirm(&window, DISCARD_QUESTION) {
+                return;
+            }
+            if let Err(err) = backup::restore(&path, backup) {
+                let message = format!("Failed to restore {}\n\n{}", path.display(), err);
+                return show_error(&window, &message);
+            }
+            fill_effective_store(&effective_tree, &effective_store, &effective::resolve_system());
+            // a hand-written backup may not parse, which leaves the window as it was, but no
+            // longer saved
+            match parse_or_ask(&window, available_families, &placeholder_pool, &path) {
+                Some(config) => {
+                    *fc_config.borrow_mut() = config;
+                    history.borrow_mut().clear();
+                    fill_aliases_store(&aliases_tree, &aliases_store, &fc_config.borrow());
+                    fill_charsets_store(&charsets_tree, &charsets_store, &fc_config.borrow());
+                }
+                None => history.borrow_mut().mark_unsaved(),
+            }
+        });
+    }
+
     let search_button_image =
         Image::new_from_icon_name("edit-find-symbolic", IconSize::Menu.into());
     let search_button = ToggleButton::new();
     search_button.set_image(&search_button_image);
 
+    let preview_entry = gtk::Entry::new();
+    preview_entry.set_placeholder_text(Some("Sample text"));
+
     let fonts_scrolled = ScrolledWindow::new(None, None);
     fonts_scrolled.set_policy(PolicyType::Never, PolicyType::Automatic);
+    fonts_scrolled.set_vexpand(true);
     let fonts_view = Viewport::new(None, None);
     let fonts_list = ListBox::new();
-    for fam in &available_families {
-        let row = ListBoxRow::new();
-        let label = Label::new(None);
-        label.set_markup(format!("{}", &fam.borrow().deref().name).as_str());
-        // todo
-        //label.set_markup(format!(
-        // "<span font_family=\"{}\">{}</span>", &fam.name, &fam.name
-        // ).as_str());
-        row.add(&label);
-
-        fonts_list.add(&row);
-    }
     fonts_view.add(&fonts_list);
     fonts_scrolled.add(&fonts_view);
 
+    // rows are added a batch at a time from the main loop so that the window shows up before
+    // pango has loaded every installed font
+    let preview_labels: Rc<RefCell<Vec<(Label, &'static RefCell<Family>)>>> =
+        Rc::new(RefCell::new(vec![]));
+    {
+        let fonts_list = fonts_list.clone();
+        let preview_entry = preview_entry.clone();
+        let preview_labels = preview_labels.clone();
+        let mut families = available_families.iter();
+        idle_add(move || {
+            let sample = preview_entry.get_text().unwrap_or_default();
+            for fam in families.by_ref().take(PREVIEW_BATCH_SIZE) {
+                let row = ListBoxRow::new();
+                let label = Label::new(None);
+                label.set_xalign(0.0);
+                label.set_markup(&preview_markup(&fam.borrow().name, &sample));
+                row.add(&label);
+                row.drag_source_set(
+                    gdk::ModifierType::BUTTON1_MASK,
+                    &drag_targets()[..TARGET_ALIAS_ENTRY as usize],
+                    gdk::DragAction::COPY,
+                );
+                row.connect_drag_data_get(move |_, _, data, _, _| {
+                    data.set_text(&fam.borrow().name, -1);
+                });
+                row.show_all();
+                // the filter function looks the family up by row index
+                preview_labels.borrow_mut().push((label, fam));
+                fonts_list.add(&row);
+            }
+            Continue(families.len() > 0)
+        });
+    }
+    {
+        let preview_labels = preview_labels.clone();
+        preview_entry.connect_changed(move |entry| {
+            let sample = entry.get_text().unwrap_or_default();
+            for &(ref label, fam) in preview_labels.borrow().iter() {
+                label.set_markup(&preview_markup(&fam.borrow().name, &sample));
+            }
+        });
+    }
+
+    let search_entry = SearchEntry::new();
+    search_entry.set_placeholder_text(Some("Name, script:Han, style:italic, mono"));
+    search_entry.set_width_chars(36);
+    let search_bar = SearchBar::new();
+    search_bar.add(&search_entry);
+    search_bar.connect_entry(&search_entry);
+
+    let font_query = Rc::new(RefCell::new(FontQuery::default()));
+    {
+        let font_query = font_query.clone();
+        let preview_labels = preview_labels.clone();
+        fonts_list.set_filter_func(Some(Box::new(move |row| {
+            match preview_labels.borrow().get(row.get_index() as usize) {
+                Some(&(_, fam)) => font_query.borrow().matches(&fam.borrow()),
+                None => true,
+            }
+        })));
+    }
+    {
+        let fonts_list = fonts_list.clone();
+        search_entry.connect_search_changed(move |entry| {
+            let text = entry.get_text().unwrap_or_default();
+            let query = match FontQuery::parse(&text) {
+                Ok(query) => query,
+                Err(err) => {
+                    entry.get_style_context().add_class("error");
+                    entry.set_tooltip_text(Some(&err));
+                    return;
+                }
+            };
+            entry.get_style_context().remove_class("error");
+            entry.set_tooltip_text(None);
+            *font_query.borrow_mut() = query;
+            fonts_list.invalidate_filter();
+        });
+    }
+    {
+        let search_bar = search_bar.clone();
+        search_button.connect_toggled(move |button| {
+            search_bar.set_search_mode(button.get_active());
+        });
+    }
+    {
+        let search_button = search_button.clone();
+        search_entry.connect_stop_search(move |_| search_button.set_active(false));
+    }
+
+    // activating a font opens its charset editor, which is how a family gets its first
+    // stripped range
+    {
+        let window = window.clone();
+        let charsets_tree = charsets_tree.clone();
+        let charsets_store = charsets_store.clone();
+        let fc_config = fc_config.clone();
+        let history = history.clone();
+        let preview_labels = preview_labels.clone();
+        fonts_list.connect_row_activated(move |_, row| {
+            let family_name = match preview_labels.borrow().get(row.get_index() as usize) {
+                Some(&(_, fam)) => fam.borrow().name.clone(),
+                None => return,
+            };
+            charset_editor::run(&window, &fc_config, &history, &family_name);
+            fill_charsets_store(&charsets_tree, &charsets_store, &fc_config.borrow());
+        });
+    }
+
+    let fonts_page = gtk::Box::new(Orientation::Vertical, 0);
+    fonts_page.add(&search_bar);
+    fonts_page.add(&preview_entry);
+    fonts_page.add(&fonts_scrolled);
+
     paned.add1(&stack);
-    paned.add2(&fonts_scrolled);
+    paned.add2(&fonts_page);
     paned.set_position(245);
 
+    header_bar.pack_start(&open_button);
+    header_bar.pack_start(&restore_button);
     header_bar.pack_start(&switcher);
     header_bar.pack_end(&search_button);
+    header_bar.pack_end(&save_as_button);
+    header_bar.pack_end(&save_button);
 
     window.set_titlebar(&header_bar);
     window.add(&paned);
 
-    // todo
-    let start = Instant::now();
+    // undoing replaces the whole config, so both stores are filled again
+    {
+        let aliases_tree = aliases_tree.clone();
+        let aliases_store = aliases_store.clone();
+        let charsets_tree = charsets_tree.clone();
+        let charsets_store = charsets_store.clone();
+        let fc_config = fc_config.clone();
+        let history = history.clone();
+        window.connect_key_press_event(move |window, event| {
+            // shift or caps lock may have turned the keyval upper case
+            let state = event.get_state();
+            if !state.contains(gdk::ModifierType::CONTROL_MASK) ||
+                gdk::keyval_to_lower(event.get_keyval()) != gdk::enums::key::z
+            {
+                return Inhibit(false);
+            }
+            // entries keep their own undo
+            if window.get_focus().map_or(false, |x| x.is::<gtk::Editable>()) {
+                return Inhibit(false);
+            }
+            let result = {
+                let mut history = history.borrow_mut();
+                let config = &mut fc_config.borrow_mut();
+                if state.contains(gdk::ModifierType::SHIFT_MASK) {
+                    history.redo(config)
+                } else {
+                    history.undo(config)
+                }
+            };
+            match result {
+                Ok(_) => {
+                    let config = fc_config.borrow();
+                    fill_aliases_store(&aliases_tree, &aliases_store, &config);
+                    fill_charsets_store(&charsets_tree, &charsets_store, &config);
+                }
+                Err(err) => show_error(window, &err.to_string()),
+            }
+            Inhibit(true)
+        });
+    }
+
     window.show_all();
-    println!(
-        "Init time: {} s",
-        Instant::now().duration_since(start).as_secs()
-    );
 
-    glib::idle_add(|| Continue(false));
+    // charsets come from fc-list, which takes a while with thousands of fonts
+    {
+        let charsets_tree = charsets_tree.clone();
+        let charsets_store = charsets_store.clone();
+        let fc_config = fc_config.clone();
+        let window = window.clone();
+        idle_add(move || {
+            if let Err(err) = deserialization::load_font_info(available_families) {
+                let message = format!("Failed to read the fonts' charsets\n\n{}", err);
+                show_error(&window, &message);
+            }
+            fill_charsets_store(&charsets_tree, &charsets_store, &fc_config.borrow());
+            Continue(false)
+        });
+    }
     gtk::main();
 }
+
+
+#[test]
+fn test_preview_markup() {
+    assert_eq!(
+        preview_markup("A&B <Sans>", ""),
+        "<span font_family=\"A&amp;B &lt;Sans&gt;\">A&amp;B &lt;Sans&gt;</span>"
+    );
+    assert_eq!(
+        preview_markup("Serif", "\"1 < 2\""),
+        "<span font_family=\"Serif\">Serif\n<big>&quot;1 &lt; 2&quot;</big></span>"
+    );
+}
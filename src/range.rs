@@ -1,22 +1,365 @@
 include!(concat!(env!("OUT_DIR"), "/ucd.rs"));
 
-#[derive(Debug)]
+use error::EditError;
+
+use std::cmp;
+use std::cmp::Ordering;
+
+
+/// The last Unicode code point.
+pub const MAX_CODE_POINT: u32 = 0x10ffff;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Range {
     Block {
         name: String,
-        code_points: (i32, i32),
+        code_points: (u32, u32),
     },
     Script {
         name: String,
-        code_points: Vec<(i32, i32)>,
+        code_points: Vec<(u32, u32)>,
     },
     Custom {
         name: String,
-        code_points: (i32, i32),
+        code_points: (u32, u32),
     },
 }
 
+impl Range {
+    pub fn name(&self) -> &str {
+        match self {
+            &Range::Block { ref name, .. } |
+            &Range::Script { ref name, .. } |
+            &Range::Custom { ref name, .. } => name,
+        }
+    }
+
+    /// A custom range from `first` to `last`, both included. The name ends up in an XML comment
+    /// of the form `name, Custom`, so it can have no commas, no `--` and no dashes or spaces at
+    /// either end.
+    pub fn custom(name: &str, first: u32, last: u32) -> Result<Range, EditError> {
+        if name.trim().is_empty() || name.trim() != name || name.contains(',') ||
+            name.contains("--") || name.starts_with('-') || name.ends_with('-')
+        {
+            return Err(EditError::InvalidRangeName(String::from(name)));
+        }
+        Ok(Range::Custom {
+            name: String::from(name),
+            code_points: bounds(first, last)?,
+        })
+    }
+
+    /// The Unicode block named `name`, matched loosely.
+    pub fn block(name: &str) -> Option<Range> {
+        block_by_name(name).map(|(first, last, name)| {
+            Range::Block {
+                name: String::from(name),
+                code_points: (first, last),
+            }
+        })
+    }
+
+    /// The Unicode script named `name`, matched loosely.
+    pub fn script(name: &str) -> Option<Range> {
+        script_by_name(name).map(|(name, code_points)| {
+            Range::Script {
+                name: String::from(name),
+                code_points: code_points.to_vec(),
+            }
+        })
+    }
+
+    /// The kind label used in typeholder range comments, e.g. `<!-- Han, Script -->`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            &Range::Block { .. } => "Block",
+            &Range::Script { .. } => "Script",
+            &Range::Custom { .. } => "Custom",
+        }
+    }
+
+    pub fn contains(&self, code_point: u32) -> bool {
+        self.code_points().iter().any(|&(first, last)| {
+            first <= code_point && code_point <= last
+        })
+    }
+
+    /// The number of code points in the range.
+    pub fn len(&self) -> u32 {
+        self.code_points()
+            .iter()
+            .map(|&(first, last)| last - first + 1)
+            .sum()
+    }
+
+    pub fn code_points(&self) -> Vec<(u32, u32)> {
+        match self {
+            &Range::Block { code_points, .. } |
+            &Range::Custom { code_points, .. } => vec![code_points],
+            &Range::Script { ref code_points, .. } => code_points.clone(),
+        }
+    }
+}
+
+/// Checks that `first..=last` is a non-empty range of Unicode code points.
+pub fn bounds(first: u32, last: u32) -> Result<(u32, u32), EditError> {
+    if first <= last && last <= MAX_CODE_POINT {
+        Ok((first, last))
+    } else {
+        Err(EditError::InvalidRange {
+            first: first,
+            last: last,
+        })
+    }
+}
+
+/// Parses a code point written as `U+4E00`, `0x4e00` or decimal `19968`.
+pub fn parse_code_point(text: &str) -> Option<u32> {
+    let text = text.trim();
+    let code_point = if text.starts_with("U+") || text.starts_with("u+") ||
+        text.starts_with("0x") || text.starts_with("0X")
+    {
+        u32::from_str_radix(&text[2..], 16).ok()?
+    } else {
+        text.parse().ok()?
+    };
+    if code_point <= MAX_CODE_POINT {
+        Some(code_point)
+    } else {
+        None
+    }
+}
+
+/// Like `parse_code_point`, with an error telling how to write one.
+pub fn checked_code_point(text: &str) -> Result<u32, EditError> {
+    parse_code_point(text).ok_or_else(|| EditError::InvalidCodePoint(String::from(text)))
+}
+
+/// A set of code points, kept as sorted ranges that neither overlap nor touch.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CodePointSet {
+    ranges: Vec<(u32, u32)>,
+}
+
+impl CodePointSet {
+    /// Normalizes `ranges`, which may be unsorted, overlapping or adjacent. Ranges that end
+    /// before they start are dropped.
+    pub fn new(mut ranges: Vec<(u32, u32)>) -> CodePointSet {
+        ranges.retain(|&(first, last)| first <= last);
+        ranges.sort();
+        let mut merged: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+        for (first, last) in ranges {
+            match merged.last_mut() {
+                Some(previous) if first <= previous.1.saturating_add(1) => {
+                    previous.1 = cmp::max(previous.1, last);
+                    continue;
+                }
+                _ => {}
+            }
+            merged.push((first, last));
+        }
+        CodePointSet { ranges: merged }
+    }
+
+    pub fn ranges(&self) -> &[(u32, u32)] {
+        &self.ranges
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The number of code points in the set.
+    pub fn len(&self) -> u32 {
+        self.ranges.iter().map(|&(first, last)| last - first + 1).sum()
+    }
+
+    pub fn contains(&self, code_point: u32) -> bool {
+        self.ranges
+            .binary_search_by(|&(first, last)| if last < code_point {
+                Ordering::Less
+            } else if first > code_point {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            })
+            .is_ok()
+    }
+
+    pub fn union(&self, other: &CodePointSet) -> CodePointSet {
+        CodePointSet::new(self.ranges.iter().chain(&other.ranges).cloned().collect())
+    }
+
+    pub fn intersection(&self, other: &CodePointSet) -> CodePointSet {
+        let mut ranges = vec![];
+        let (mut i, mut j) = (0, 0);
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let (a, b) = (self.ranges[i], other.ranges[j]);
+            let first = cmp::max(a.0, b.0);
+            let last = cmp::min(a.1, b.1);
+            if first <= last {
+                ranges.push((first, last));
+            }
+            if a.1 < b.1 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        CodePointSet { ranges: ranges }
+    }
+
+    pub fn difference(&self, other: &CodePointSet) -> CodePointSet {
+        let mut ranges = vec![];
+        let mut others = other.ranges.iter().peekable();
+        for &(first, last) in &self.ranges {
+            let mut first = first;
+            // ranges of `other` ending before `first` can't overlap later ranges either
+            while others.peek().map_or(false, |x| x.1 < first) {
+                others.next();
+            }
+            for &(x, y) in others.clone() {
+                if x > last {
+                    break;
+                }
+                if x > first {
+                    ranges.push((first, x - 1));
+                }
+                if y >= last {
+                    first = last + 1;
+                    break;
+                }
+                first = y + 1;
+            }
+            if first <= last {
+                ranges.push((first, last));
+            }
+        }
+        CodePointSet { ranges: ranges }
+    }
+}
+
+impl<'a> From<&'a Range> for CodePointSet {
+    fn from(range: &'a Range) -> CodePointSet {
+        CodePointSet::new(range.code_points())
+    }
+}
+
+/// The block containing `code_point`.
+pub fn block_of(code_point: u32) -> Option<&'static str> {
+    find_containing(BLOCKS, code_point)
+}
+
+/// The script of `code_point`, its Script property value without extensions.
+pub fn script_of(code_point: u32) -> Option<&'static str> {
+    find_containing(SCRIPT_RANGES, code_point)
+}
+
+/// Where `code_point` sits in Unicode, its script and block like `Han, CJK Unified Ideographs`.
+pub fn code_point_context(code_point: u32) -> String {
+    let script = script_of(code_point).unwrap_or("Unknown");
+    match block_of(code_point) {
+        Some(block) => format!("{}, {}", script, block),
+        None => String::from(script),
+    }
+}
+
+/// Looks a block up by name, matched loosely, so "latin_1_supplement" finds
+/// "Latin-1 Supplement".
+pub fn block_by_name(name: &str) -> Option<(u32, u32, &'static str)> {
+    BLOCKS.iter().cloned().find(|&(_, _, x)| loose_eq(x, name))
+}
+
+/// Looks a script up by name, matched loosely.
+pub fn script_by_name(name: &str) -> Option<(&'static str, &'static [(u32, u32)])> {
+    SCRIPTS.iter().cloned().find(|&(x, _)| loose_eq(x, name))
+}
+
+fn find_containing(table: &[(u32, u32, &'static str)], code_point: u32) -> Option<&'static str> {
+    table
+        .binary_search_by(|&(first, last, _)| if last < code_point {
+            Ordering::Less
+        } else if first > code_point {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        })
+        .ok()
+        .map(|i| table[i].2)
+}
+
+/// UAX #44 loose matching of property values, as Blocks.txt asks for: case, whitespace,
+/// underscores and hyphens are ignored.
+pub fn loose_eq(x: &str, y: &str) -> bool {
+    let significant = |c: &char| !c.is_whitespace() && *c != '_' && *c != '-';
+    let mut x = x.chars().filter(&significant).flat_map(char::to_lowercase);
+    let mut y = y.chars().filter(&significant).flat_map(char::to_lowercase);
+    loop {
+        match (x.next(), y.next()) {
+            (None, None) => return true,
+            (a, b) if a != b => return false,
+            _ => {}
+        }
+    }
+}
+
 lazy_static! {
-    pub static ref UNICODE_BLOCKS: Vec<Range> = unicode_blocks!();
-    pub static ref UNICODE_SCRIPTS: Vec<Range> = unicode_scripts!();
+    pub static ref UNICODE_BLOCKS: Vec<Range> = BLOCKS
+        .iter()
+        .filter_map(|&(_, _, name)| Range::block(name))
+        .collect();
+    pub static ref UNICODE_SCRIPTS: Vec<Range> = SCRIPTS
+        .iter()
+        .filter_map(|&(name, _)| Range::script(name))
+        .collect();
+}
+
+
+#[test]
+fn test_lookups() {
+    assert_eq!(block_of(0x4e2d), Some("CJK Unified Ideographs"));
+    assert_eq!(script_of(0x4e2d), Some("Han"));
+    assert_eq!(script_of(0x41), Some("Latin"));
+    assert_eq!(block_of(0x10ffff), Some("Supplementary Private Use Area-B"));
+    // a noncharacter in an unassigned plane, never part of a block
+    assert_eq!(block_of(0xdffff), None);
+    assert_eq!(code_point_context(0x4e2d), "Han, CJK Unified Ideographs");
+    assert_eq!(code_point_context(0xdffff), "Unknown");
+
+    assert_eq!(
+        block_by_name("latin_1_supplement"),
+        Some((0x80, 0xff, "Latin-1 Supplement"))
+    );
+    assert_eq!(block_by_name("LATIN 1"), None);
+    assert_eq!(script_by_name("old_italic").map(|x| x.0), Some("Old_Italic"));
+}
+
+#[test]
+fn test_code_point_set() {
+    assert_eq!(parse_code_point("U+4E00"), Some(0x4e00));
+    assert_eq!(parse_code_point("0x4e00"), Some(0x4e00));
+    assert_eq!(parse_code_point("19968"), Some(0x4e00));
+    assert_eq!(parse_code_point("4e00"), None);
+    assert_eq!(parse_code_point("-1"), None);
+    assert_eq!(parse_code_point("0x110000"), None);
+    assert!(Range::custom("Reversed", 0x9fff, 0x4e00).is_err());
+    assert!(Range::custom("Beyond", 0x10fff0, 0x110000).is_err());
+    for name in &["CJK, extended", "CJK -- extended", "-CJK", "CJK-", " CJK", ""] {
+        assert!(Range::custom(name, 0x4e00, 0x9fff).is_err());
+    }
+
+    let set = CodePointSet::new(vec![(0x30, 0x39), (0x20, 0x2f), (0x41, 0x5a), (0x45, 0x60)]);
+    assert_eq!(set.ranges(), &[(0x20, 0x39), (0x41, 0x60)]);
+    assert!(set.contains(0x41) && !set.contains(0x40));
+    let other = CodePointSet::new(vec![(0x30, 0x44), (0x50, 0x50), (0x5f, 0x7e)]);
+    assert_eq!(set.union(&other).ranges(), &[(0x20, 0x7e)]);
+    assert_eq!(
+        set.intersection(&other).ranges(),
+        &[(0x30, 0x39), (0x41, 0x44), (0x50, 0x50), (0x5f, 0x60)]
+    );
+    assert_eq!(
+        set.difference(&other).ranges(),
+        &[(0x20, 0x2f), (0x45, 0x4f), (0x51, 0x5e)]
+    );
+    assert_eq!(set.difference(&other).len(), 0x10 + 0xb + 0xe);
 }
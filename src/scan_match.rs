@@ -0,0 +1,122 @@
+use error::EditError;
+use family;
+use family::Family;
+use range::{CodePointSet, Range};
+
+use std::cell::RefCell;
+
+
+/// What a `<match target="scan">` strips from a family. The config keeps these rather than the
+/// family, so that configs parsed with the same families don't change each other.
+#[derive(Debug, PartialEq)]
+pub struct ScanMatch<'a> {
+    pub family: &'a RefCell<Family>,
+    pub stripped_ranges: Vec<Range>,
+    /// Languages taken out of the family's `lang` property, like `ja` or `zh-tw`.
+    pub stripped_languages: Vec<String>,
+}
+
+impl<'a> ScanMatch<'a> {
+    pub fn new(family: &'a RefCell<Family>) -> ScanMatch<'a> {
+        ScanMatch {
+            family: family,
+            stripped_ranges: vec![],
+            stripped_languages: vec![],
+        }
+    }
+
+    /// Whether a stripped range removes `code_point` from the family.
+    pub fn strips(&self, code_point: u32) -> bool {
+        self.stripped_ranges.iter().any(|x| x.contains(code_point))
+    }
+
+    /// Every code point the stripped ranges remove.
+    pub fn stripped_code_points(&self) -> CodePointSet {
+        self.stripped_ranges.iter().fold(CodePointSet::default(), |x, y| {
+            x.union(&CodePointSet::from(y))
+        })
+    }
+
+    /// Stripped ranges that remove nothing the others don't, e.g. a Custom range inside a
+    /// stripped Script. Of ranges covering each other, the later one is kept.
+    pub fn redundant_ranges(&self) -> Vec<&Range> {
+        let mut redundant: Vec<&Range> = vec![];
+        for (index, range) in self.stripped_ranges.iter().enumerate() {
+            let others = self.stripped_ranges
+                .iter()
+                .enumerate()
+                .filter(|&(i, x)| {
+                    i != index && !redundant.iter().any(|y| *y as *const _ == x as *const _)
+                })
+                .fold(CodePointSet::default(), |x, (_, y)| {
+                    x.union(&CodePointSet::from(y))
+                });
+            let code_points = CodePointSet::from(range);
+            if code_points.difference(&others).is_empty() {
+                redundant.push(range);
+            }
+        }
+        redundant
+    }
+
+    pub fn add_range(&mut self, range: Range) -> Result<(), EditError> {
+        if self.range_position(range.name(), range.kind()).is_some() {
+            return Err(EditError::DuplicateRange {
+                family: self.family.borrow().name.clone(),
+                range: format!("{}, {}", range.name(), range.kind()),
+            });
+        }
+        self.stripped_ranges.push(range);
+        Ok(())
+    }
+
+    pub fn remove_range(&mut self, name: &str, kind: &str) -> Result<Range, EditError> {
+        match self.range_position(name, kind) {
+            Some(index) => Ok(self.stripped_ranges.remove(index)),
+            None => Err(EditError::UnknownRange {
+                family: self.family.borrow().name.clone(),
+                range: format!("{}, {}", name, kind),
+            }),
+        }
+    }
+
+    /// Takes `language` out of the family's `lang` property. Tags are normalized as fontconfig
+    /// does, so "zh_TW" strips `zh-tw`.
+    pub fn add_language(&mut self, language: &str) -> Result<(), EditError> {
+        let language = family::normalize_language(language).ok_or_else(|| {
+            EditError::InvalidLanguage(String::from(language))
+        })?;
+        if self.stripped_languages.contains(&language) {
+            return Err(EditError::DuplicateRange {
+                family: self.family.borrow().name.clone(),
+                range: format!("{}, Language", language),
+            });
+        }
+        self.stripped_languages.push(language);
+        Ok(())
+    }
+
+    pub fn remove_language(&mut self, language: &str) -> Result<String, EditError> {
+        let position = family::normalize_language(language).and_then(|x| {
+            self.stripped_languages.iter().position(|y| *y == x)
+        });
+        match position {
+            Some(index) => Ok(self.stripped_languages.remove(index)),
+            None => Err(EditError::UnknownRange {
+                family: self.family.borrow().name.clone(),
+                range: format!("{}, Language", language),
+            }),
+        }
+    }
+
+    /// Whether the match strips neither ranges nor languages.
+    pub fn strips_nothing(&self) -> bool {
+        self.stripped_ranges.is_empty() && self.stripped_languages.is_empty()
+    }
+
+    pub fn range_position(&self, name: &str, kind: &str) -> Option<usize> {
+        self.stripped_ranges.iter().position(|x| {
+            x.name() == name && x.kind() == kind
+        })
+    }
+}
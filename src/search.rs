@@ -0,0 +1,104 @@
+use family::Family;
+use range::Range;
+
+#[cfg(test)]
+use range::CodePointSet;
+#[cfg(test)]
+use std::cell::RefCell;
+
+
+/// Share of a script's code points a family needs for `script:` to match it.
+const SCRIPT_COVERAGE_THRESHOLD: f64 = 0.5;
+
+/// A parsed search of the fonts list: words match family names, `script:<name>`, `style:<name>`
+/// and `mono` filter by what fontconfig reports about the family.
+#[derive(Debug, Default, PartialEq)]
+pub struct FontQuery {
+    pub words: Vec<String>,
+    pub script: Option<Range>,
+    pub style: Option<String>,
+    pub monospace: bool,
+}
+
+impl FontQuery {
+    pub fn parse(text: &str) -> Result<FontQuery, String> {
+        let mut query = FontQuery::default();
+        for word in text.split_whitespace() {
+            let lowercase = word.to_lowercase();
+            if lowercase.starts_with("script:") {
+                let name = &word["script:".len()..];
+                let script = Range::script(name).ok_or_else(
+                    || format!("Unknown script \"{}\"", name),
+                )?;
+                query.script = Some(script);
+            } else if lowercase.starts_with("style:") {
+                query.style = Some(String::from(&lowercase["style:".len()..]));
+            } else if lowercase == "mono" || lowercase == "monospace" {
+                query.monospace = true;
+            } else {
+                query.words.push(lowercase);
+            }
+        }
+        Ok(query)
+    }
+
+    /// Whether the query needs `deserialization::load_font_info` to have run.
+    pub fn needs_font_info(&self) -> bool {
+        self.script.is_some() || self.style.is_some() || self.monospace
+    }
+
+    pub fn matches(&self, family: &Family) -> bool {
+        let name = family.name.to_lowercase();
+        if !self.words.iter().all(|x| name.contains(x.as_str())) &&
+            !fuzzy_matches(&self.words.concat(), &name)
+        {
+            return false;
+        }
+        if self.monospace && !family.monospace {
+            return false;
+        }
+        if let Some(ref style) = self.style {
+            if !family.styles.iter().any(
+                |x| x.to_lowercase().contains(style.as_str()),
+            )
+            {
+                return false;
+            }
+        }
+        if let Some(ref script) = self.script {
+            if family.coverage_ratio(script) < SCRIPT_COVERAGE_THRESHOLD {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Whether the characters of `pattern` appear in order in `text`, e.g. "njp" in "Noto Sans JP".
+fn fuzzy_matches(pattern: &str, text: &str) -> bool {
+    let mut chars = text.chars();
+    pattern.chars().all(|x| chars.any(|y| y == x))
+}
+
+
+#[test]
+fn test_font_query() {
+    let family = RefCell::new(Family::new("Noto Sans Mono CJK SC"));
+    {
+        let mut family = family.borrow_mut();
+        family.monospace = true;
+        family.styles = vec![String::from("Regular"), String::from("Bold")];
+        family.charset =
+            CodePointSet::new(vec![(0x20, 0x7e), (0x3000, 0x303f), (0xac00, 0xd7a3)]);
+    }
+    let matches = |text: &str| FontQuery::parse(text).unwrap().matches(&family.borrow());
+
+    assert!(matches("sans cjk"));
+    assert!(matches("nscjk"));
+    assert!(!matches("serif"));
+    assert!(matches("mono style:bold"));
+    assert!(!matches("style:italic"));
+    assert!(matches("script:hangul"));
+    assert!(!matches("script:Hiragana"));
+    assert!(FontQuery::parse("script:Klingon").is_err());
+}
@@ -0,0 +1,418 @@
+use alias::{Alias, Binding, FamilyList, LanguageRule, FAMILY_LISTS};
+use backup;
+use config::Config;
+use consts::*;
+use deserialization;
+use error::ParseError;
+#[cfg(test)]
+use family::Family;
+use itertools::Itertools;
+use range::{CodePointSet, Range};
+use scan_match::ScanMatch;
+use sxd_document::Package;
+use sxd_document::dom::ChildOfElement;
+use sxd_document::dom::Comment;
+use sxd_document::dom::Document;
+use sxd_document::dom::Element;
+use sxd_document::parser;
+use sxd_document::writer;
+
+#[cfg(test)]
+use std::cell::RefCell;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+
+const FONTS_CONF_HEADER: &str = "<?xml version='1.0' encoding='UTF-8'?>
+<!DOCTYPE fontconfig SYSTEM 'fonts.dtd'>
+";
+
+const INDENT: &str = "    ";
+
+/// Saves `config` over the file at `path`, backing up what was there first.
+pub fn save(config: &Config, path: &Path) -> io::Result<()> {
+    backup::back_up(path)?;
+    write_to(config, path)
+}
+
+pub fn write_to(config: &Config, path: &Path) -> io::Result<()> {
+    let text = to_string(config).map_err(
+        |err| io::Error::new(io::ErrorKind::InvalidData, err),
+    )?;
+    write_atomically(path, text.as_bytes())
+}
+
+/// Writes a temporary file next to `path` and renames it over `path`, so that a crash leaves
+/// either the old file or the new one but never half of it. A symlink at `path`, as dotfile
+/// managers make, is kept and its target replaced.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file_name = path.file_name().map_or(String::new(), |x| {
+        x.to_string_lossy().into_owned()
+    });
+    let temp_path = path.with_file_name(format!(".{}.typeholder-tmp", file_name));
+    {
+        let mut f = File::create(&temp_path)?;
+        f.write_all(contents)?;
+        f.sync_all()?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+        }
+    }
+    fs::rename(&temp_path, &path).map_err(|err| {
+        fs::remove_file(&temp_path).ok();
+        err
+    })
+}
+
+/// The fonts.conf text of `config`, an error when its residue does not read back.
+pub fn to_string(config: &Config) -> Result<String, ParseError> {
+    // `residue` is borrowed, so work on a fresh copy of it
+    let residue = format_package(&config.residue);
+    let package = match parser::parse(&residue) {
+        Ok(package) => package,
+        Err((position, errors)) => {
+            return Err(deserialization::xml_error(&residue, position, errors))
+        }
+    };
+
+    {
+        let doc = package.as_document();
+        let root_element = deserialization::root_element(doc).ok_or_else(|| {
+            ParseError::MissingChild {
+                element: String::from("#document"),
+                child: String::from("fontconfig"),
+            }
+        })?;
+
+        // re-appending moves each residue child to the end, indented
+        for child in root_element.children() {
+            append_indented(root_element, child, 1);
+        }
+        for alias in &config.aliases {
+            // prepending before the <alias> runs puts the rules ahead of its prefer list
+            for rule in &alias.language_rules {
+                append_indented(root_element, typeholder_comment(doc), 1);
+                append_indented(root_element, language_rule_element(doc, alias, rule), 1);
+            }
+            append_indented(root_element, typeholder_comment(doc), 1);
+            append_indented(root_element, alias_element(doc, alias), 1);
+        }
+        for scan_match in &config.scan_matches {
+            append_indented(root_element, typeholder_comment(doc), 1);
+            append_indented(root_element, scan_match_element(doc, scan_match), 1);
+        }
+        close_indented(root_element, 0);
+    }
+
+    Ok(format!("{}{}\n", FONTS_CONF_HEADER, format_package(&package)))
+}
+
+/// Formats a package without the XML declaration, which `to_string` writes itself.
+pub fn format_package(package: &Package) -> String {
+    let mut buffer = vec![];
+    writer::format_document(&package.as_document(), &mut buffer).expect(INVALID_CONFIG);
+    let text = String::from_utf8(buffer).expect(INVALID_CONFIG);
+    match text.find("?>") {
+        Some(i) => String::from(&text[i + 2..]),
+        None => text,
+    }
+}
+
+fn typeholder_comment<'d>(doc: Document<'d>) -> Comment<'d> {
+    doc.create_comment(TYPEHOLDER_COMMENT_PREFIX)
+}
+
+fn alias_element<'d>(doc: Document<'d>, alias: &Alias) -> Element<'d> {
+    let alias_elem = doc.create_element("alias");
+    if alias.binding != Binding::Weak {
+        alias_elem.set_attribute_value("binding", alias.binding.name());
+    }
+    append_indented(alias_elem, text_element(doc, "family", &alias.name), 2);
+
+    for &list in FAMILY_LISTS.iter() {
+        // <prefer> is kept even when empty, as in DEFAULT_FONTS_CONF
+        if list != FamilyList::Prefer && alias.list(list).is_empty() {
+            continue;
+        }
+        let list_elem = doc.create_element(list.name());
+        for entry in alias.list(list) {
+            append_indented(list_elem, text_element(doc, "family", &entry.name()), 3);
+        }
+        close_indented(list_elem, 2);
+        append_indented(alias_elem, list_elem, 2);
+    }
+
+    close_indented(alias_elem, 1);
+    alias_elem
+}
+
+fn language_rule_element<'d>(
+    doc: Document<'d>,
+    alias: &Alias,
+    rule: &LanguageRule,
+) -> Element<'d> {
+    let match_elem = doc.create_element("match");
+    match_elem.set_attribute_value("target", "pattern");
+
+    let lang_test_elem = doc.create_element("test");
+    lang_test_elem.set_attribute_value("name", "lang");
+    lang_test_elem.set_attribute_value("compare", "contains");
+    lang_test_elem.append_child(text_element(doc, "string", &rule.language));
+    append_indented(match_elem, lang_test_elem, 2);
+
+    let family_test_elem = doc.create_element("test");
+    family_test_elem.set_attribute_value("name", "family");
+    family_test_elem.append_child(text_element(doc, "string", &alias.name));
+    append_indented(match_elem, family_test_elem, 2);
+
+    let edit_elem = doc.create_element("edit");
+    edit_elem.set_attribute_value("name", "family");
+    edit_elem.set_attribute_value("mode", "prepend");
+    for family in &rule.families {
+        append_indented(edit_elem, text_element(doc, "string", &family.borrow().name), 3);
+    }
+    close_indented(edit_elem, 2);
+    append_indented(match_elem, edit_elem, 2);
+
+    close_indented(match_elem, 1);
+    match_elem
+}
+
+fn scan_match_element<'d>(doc: Document<'d>, scan_match: &ScanMatch) -> Element<'d> {
+    let family = scan_match.family.borrow();
+
+    let match_elem = doc.create_element("match");
+    match_elem.set_attribute_value("target", "scan");
+
+    let test_elem = doc.create_element("test");
+    test_elem.set_attribute_value("name", "family");
+    test_elem.append_child(text_element(doc, "string", &family.name));
+    append_indented(match_elem, test_elem, 2);
+
+    // each range only writes what earlier ones don't strip yet, so overlapping ranges make no
+    // duplicate <range>s; the comment of a range cut short lists its code points instead
+    let charset_elem = doc.create_element("charset");
+    let mut written = CodePointSet::default();
+    for range in &scan_match.stripped_ranges {
+        let code_points = CodePointSet::from(range);
+        let remaining = code_points.difference(&written);
+        let listed = if remaining == code_points {
+            None
+        } else {
+            Some(&code_points)
+        };
+        append_indented(charset_elem, range_comment(doc, range, listed), 5);
+        for &(start, end) in remaining.ranges() {
+            let range_elem = doc.create_element("range");
+            range_elem.append_child(text_element(doc, "int", &format!("0x{:x}", start)));
+            range_elem.append_child(text_element(doc, "int", &format!("0x{:x}", end)));
+            append_indented(charset_elem, range_elem, 5);
+        }
+        written = written.union(&code_points);
+    }
+    close_indented(charset_elem, 4);
+    if !scan_match.stripped_ranges.is_empty() {
+        append_indented(match_elem, minus_edit(doc, "charset", charset_elem), 2);
+    }
+
+    if !scan_match.stripped_languages.is_empty() {
+        let langset_elem = doc.create_element("langset");
+        for language in &scan_match.stripped_languages {
+            append_indented(langset_elem, text_element(doc, "string", language), 5);
+        }
+        close_indented(langset_elem, 4);
+        append_indented(match_elem, minus_edit(doc, "lang", langset_elem), 2);
+    }
+
+    close_indented(match_elem, 1);
+    match_elem
+}
+
+/// An `<edit>` assigning `property` minus the values in `set_elem`.
+fn minus_edit<'d>(doc: Document<'d>, property: &str, set_elem: Element<'d>) -> Element<'d> {
+    let minus_elem = doc.create_element("minus");
+    append_indented(minus_elem, text_element(doc, "name", property), 4);
+    append_indented(minus_elem, set_elem, 4);
+    close_indented(minus_elem, 3);
+
+    let edit_elem = doc.create_element("edit");
+    edit_elem.set_attribute_value("name", property);
+    edit_elem.set_attribute_value("mode", "assign");
+    append_indented(edit_elem, minus_elem, 3);
+    close_indented(edit_elem, 2);
+    edit_elem
+}
+
+/// The `name, Block|Script|Custom` comment the stripped ranges are grouped by when parsed,
+/// followed by `listed` code points when the `<range>`s after it leave some out.
+fn range_comment<'d>(
+    doc: Document<'d>,
+    range: &Range,
+    listed: Option<&CodePointSet>,
+) -> Comment<'d> {
+    let mut text = format!(" {}, {}", range.name(), range.kind());
+    if let Some(code_points) = listed {
+        text.push_str(", ");
+        text.push_str(&code_points
+            .ranges()
+            .iter()
+            .map(|&(first, last)| format!("U+{:04X}..U+{:04X}", first, last))
+            .join(" "));
+    }
+    text.push(' ');
+    doc.create_comment(&text)
+}
+
+fn text_element<'d>(doc: Document<'d>, name: &str, text: &str) -> Element<'d> {
+    let elem = doc.create_element(name);
+    elem.append_child(doc.create_text(text));
+    elem
+}
+
+fn append_indented<'d, C>(parent: Element<'d>, child: C, depth: usize)
+where
+    C: Into<ChildOfElement<'d>>,
+{
+    parent.append_child(
+        parent.document().create_text(&format!("\n{}", INDENT.repeat(depth))),
+    );
+    parent.append_child(child);
+}
+
+fn close_indented(parent: Element, depth: usize) {
+    parent.append_child(
+        parent.document().create_text(&format!("\n{}", INDENT.repeat(depth))),
+    );
+}
+
+
+#[test]
+fn test_round_trip() {
+    let fonts_conf = "<?xml version='1.0' encoding='UTF-8'?>
+<!DOCTYPE fontconfig SYSTEM 'fonts.dtd'>
+<fontconfig>
+    <dir>~/.fonts</dir>
+    <!-- Generated by Typeholder, DO NOT EDIT -->
+    <match target=\"pattern\">
+        <test name=\"lang\" compare=\"contains\"><string>zh-cn</string></test>
+        <test name=\"family\"><string>sans-serif</string></test>
+        <edit name=\"family\" mode=\"prepend\">
+            <string>Noto Sans CJK SC</string>
+        </edit>
+    </match>
+    <!-- Generated by Typeholder, DO NOT EDIT -->
+    <alias binding=\"strong\">
+        <family>sans-serif</family>
+        <prefer>
+            <family>DejaVu Sans</family>
+            <family>Noto Sans CJK SC</family>
+            <family>Gone Sans</family>
+        </prefer>
+        <default>
+            <family>DejaVu Sans</family>
+        </default>
+    </alias>
+    <!-- Generated by Typeholder, DO NOT EDIT -->
+    <match target=\"scan\">
+        <test name=\"family\"><string>DejaVu Sans</string></test>
+        <edit name=\"charset\" mode=\"assign\">
+            <minus>
+                <name>charset</name>
+                <charset>
+                    <!-- CJK Symbols and Punctuation, Block -->
+                    <range><int>0x3000</int><int>0x303f</int></range>
+                    <!-- Hangul, Script -->
+                    <range><int>0x1100</int><int>0x11ff</int></range>
+                    <range><int>0xac00</int><int>0xd7a3</int></range>
+                    <!-- Quotes, Custom -->
+                    <range><int>0x2018</int><int>0x201f</int></range>
+                    <!-- Brackets, Custom -->
+                    <range><int>0x3008</int><int>0x3011</int></range>
+                </charset>
+            </minus>
+        </edit>
+        <edit name=\"lang\" mode=\"assign\">
+            <minus>
+                <name>lang</name>
+                <langset>
+                    <string>ja</string>
+                </langset>
+            </minus>
+        </edit>
+    </match>
+    <!-- Generated by Typeholder, DO NOT EDIT -->
+    <match target=\"scan\">
+        <test name=\"family\"><string>Gone Sans</string></test>
+        <edit name=\"charset\" mode=\"assign\">
+            <minus>
+                <name>charset</name>
+                <charset>
+                    <!-- Quotes, Custom -->
+                    <range><int>0x2018</int><int>0x201f</int></range>
+                </charset>
+            </minus>
+        </edit>
+    </match>
+</fontconfig>
+";
+    let families = || {
+        vec![
+            RefCell::new(Family::new("DejaVu Sans")),
+            RefCell::new(Family::new("Noto Sans CJK SC")),
+        ]
+    };
+
+    let first_families = families();
+    let first_placeholders = deserialization::placeholders(&[fonts_conf], &first_families);
+    let mut first = deserialization::parse(fonts_conf, &first_families, &first_placeholders)
+        .unwrap();
+    assert_eq!(first.aliases[0].prefer_list.len(), 3);
+    assert_eq!(first.aliases[0].default_list.len(), 1);
+    assert_eq!(first.aliases[0].binding, Binding::Strong);
+    assert_eq!(first.aliases[0].language_rules[0].families, vec![&first_families[1]]);
+    assert_eq!(first.scan_matches[0].stripped_ranges.len(), 4);
+    assert_eq!(first.scan_matches[0].redundant_ranges().len(), 1);
+    assert_eq!(first.scan_matches[0].stripped_languages, vec!["ja"]);
+    // families that are not installed are kept through placeholders
+    assert!(!first.scan_matches[1].family.borrow().installed);
+    assert_eq!(first.aliases[0].prefer_list[2].family(), Some(first.scan_matches[1].family));
+
+    // anything but commas and dashes that would end the comment goes in a range name
+    let range = Range::custom("<Dots> & - -dashes- 'n' \"stuff\"", 0x2024, 0x2027).unwrap();
+    first.strip_range("DejaVu Sans", range).unwrap();
+
+    // the brackets are inside the block, so only the comment keeps them
+    let text = to_string(&first).unwrap();
+    assert!(text.contains("<!-- Brackets, Custom, U+3008..U+3011 -->"));
+    assert!(!text.contains("<int>0x3008</int>"));
+
+    let second_families = families();
+    let second_placeholders = deserialization::placeholders(&[&text], &second_families);
+    let second = deserialization::parse(&text, &second_families, &second_placeholders).unwrap();
+    assert_eq!(first, second);
+
+    // parsing leaves other configs with the same families alone, whether it fails or not
+    let broken = text.replace("<int>0x3000</int>", "<int>0xzz</int>");
+    assert!(deserialization::parse(&broken, &second_families, &second_placeholders).is_err());
+    deserialization::parse(DEFAULT_FONTS_CONF, &first_families, &first_placeholders).unwrap();
+    assert_eq!(first, second);
+
+    // a custom range is a single <range>, the second one is not quietly dropped
+    let split = fonts_conf.replacen(
+        "<range><int>0x2018</int><int>0x201f</int></range>",
+        "<range><int>0x2018</int><int>0x2019</int></range>\
+         <range><int>0x201c</int><int>0x201d</int></range>",
+        1,
+    );
+    match deserialization::parse(&split, &first_families, &first_placeholders) {
+        Err(ParseError::SeveralRanges { ref name, .. }) => assert_eq!(name, "Quotes"),
+        x => panic!("{:?}", x),
+    }
+}
@@ -0,0 +1,213 @@
+use alias::{Binding, Entry, FAMILY_LISTS};
+use config::Config;
+use error::EditError;
+use family;
+use family::Family;
+
+use std::cell::RefCell;
+
+#[cfg(test)]
+use alias::FamilyList;
+#[cfg(test)]
+use consts::DEFAULT_FONTS_CONF;
+#[cfg(test)]
+use deserialization;
+#[cfg(test)]
+use range::{CodePointSet, Range};
+
+
+/// The family that ends up drawing one character, `None` when nothing in the alias can.
+#[derive(Debug, PartialEq)]
+pub struct Resolved<'a> {
+    pub character: char,
+    pub family: Option<&'a RefCell<Family>>,
+}
+
+/// The families fontconfig would try for `alias`, in order: the prefer, accept and default
+/// lists, with aliases in them expanded in place.
+pub fn candidates<'a>(
+    config: &Config<'a>,
+    alias: &str,
+) -> Result<Vec<&'a RefCell<Family>>, EditError> {
+    Ok(bound_candidates(config, alias, None)?.into_iter().map(|x| x.0).collect())
+}
+
+/// The candidates for text in `language`, with the families of the language rules that apply
+/// ahead of the alias' own lists. fontconfig ranks the language of a font above families bound
+/// weakly, so families that suit the language come first unless the alias binds them strongly,
+/// see `Family::language_distance`.
+pub fn candidates_for_language<'a>(
+    config: &Config<'a>,
+    alias: &str,
+    language: &str,
+) -> Result<Vec<&'a RefCell<Family>>, EditError> {
+    let language = family::normalize_language(language).ok_or_else(|| {
+        EditError::InvalidLanguage(String::from(language))
+    })?;
+    let mut candidates = bound_candidates(config, alias, Some(&language))?
+        .into_iter()
+        .enumerate()
+        .collect::<Vec<_>>();
+    // the sort is stable, so the list order still counts among families suiting it as well
+    candidates.sort_by_key(|&(i, (family, binding))| match binding {
+        Binding::Weak => {
+            let stripped = config.stripped_languages(family);
+            (1, family.borrow().language_distance(&language, stripped), i)
+        }
+        _ => (0, 0, i),
+    });
+    Ok(candidates.into_iter().map(|(_, (family, _))| family).collect())
+}
+
+// each candidate with the binding of the alias that lists it
+fn bound_candidates<'a>(
+    config: &Config<'a>,
+    alias: &str,
+    language: Option<&str>,
+) -> Result<Vec<(&'a RefCell<Family>, Binding)>, EditError> {
+    let mut families = vec![];
+    let mut visited = vec![];
+    expand(config, alias, language, &mut families, &mut visited)?;
+    Ok(families)
+}
+
+fn expand<'a>(
+    config: &Config<'a>,
+    alias: &str,
+    language: Option<&str>,
+    families: &mut Vec<(&'a RefCell<Family>, Binding)>,
+    visited: &mut Vec<String>,
+) -> Result<(), EditError> {
+    if visited.iter().any(|x| x == alias) {
+        return Ok(());
+    }
+    visited.push(String::from(alias));
+    let alias = config.alias(alias)?;
+    let push = |families: &mut Vec<(&'a RefCell<Family>, Binding)>, family, binding| {
+        if !families.iter().any(|x| x.0 as *const _ == family as *const _) {
+            families.push((family, binding));
+        }
+    };
+
+    // rules are written before their alias and prepend weakly, ahead of the prefer list
+    let rules = alias.language_rules.iter().filter(|x| {
+        language.map_or(false, |y| x.applies_to(y))
+    });
+    for family in rules.flat_map(|x| x.families.iter()) {
+        push(families, *family, Binding::Weak);
+    }
+    for &list in FAMILY_LISTS.iter() {
+        for entry in alias.list(list) {
+            match entry {
+                &Entry::Family(family) => push(families, family, alias.binding),
+                &Entry::Alias(ref name) => expand(config, name, language, families, visited)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Works out which family draws each character of `sample` when asking for `alias`, for text
+/// in `language` if given: the first candidate that covers the character and doesn't strip it.
+/// Works on unsaved edits, but needs charsets and languages from
+/// `deserialization::load_font_info`.
+pub fn simulate<'a>(
+    config: &Config<'a>,
+    alias: &str,
+    sample: &str,
+    language: Option<&str>,
+) -> Result<Vec<Resolved<'a>>, EditError> {
+    let candidates = match language {
+        Some(language) => candidates_for_language(config, alias, language)?,
+        None => candidates(config, alias)?,
+    };
+    Ok(
+        sample
+            .chars()
+            .map(|character| {
+                let code_point = character as u32;
+                Resolved {
+                    character: character,
+                    family: candidates.iter().cloned().find(|x| {
+                        x.borrow().covers(code_point) && !config.strips(x, code_point)
+                    }),
+                }
+            })
+            .collect(),
+    )
+}
+
+
+#[test]
+fn test_simulate() {
+    let families = vec![
+        RefCell::new(Family::new("DejaVu Sans")),
+        RefCell::new(Family::new("Noto Sans CJK SC")),
+        RefCell::new(Family::new("Noto Sans CJK TC")),
+    ];
+    families[0].borrow_mut().charset = CodePointSet::new(vec![(0x20, 0x7e), (0x4e00, 0x9fff)]);
+    families[1].borrow_mut().charset =
+        CodePointSet::new(vec![(0x20, 0x7e), (0x3000, 0x303f), (0x4e00, 0x9fff)]);
+    families[2].borrow_mut().charset = CodePointSet::new(vec![(0x20, 0x7e)]);
+
+    let placeholders = vec![];
+    let mut config = deserialization::parse(DEFAULT_FONTS_CONF, &families, &placeholders).unwrap();
+    config.add_alias("Brand UI").unwrap();
+    config
+        .insert_family("Brand UI", FamilyList::Prefer, "sans-serif", None)
+        .unwrap();
+    config
+        .insert_family("sans-serif", FamilyList::Prefer, "DejaVu Sans", None)
+        .unwrap();
+    config
+        .insert_family("sans-serif", FamilyList::Default, "Noto Sans CJK SC", None)
+        .unwrap();
+    config
+        .strip_range(
+            "DejaVu Sans",
+            Range::Custom {
+                name: String::from("CJK"),
+                code_points: (0x4e00, 0x9fff),
+            },
+        )
+        .unwrap();
+
+    let resolved = simulate(&config, "Brand UI", "A中。😀", None).unwrap();
+    let names = resolved
+        .iter()
+        .map(|x| x.family.map(|y| y.borrow().name.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        vec![
+            Some(String::from("DejaVu Sans")),
+            Some(String::from("Noto Sans CJK SC")),
+            Some(String::from("Noto Sans CJK SC")),
+            None,
+        ]
+    );
+    assert!(simulate(&config, "cursive", "A", None).is_err());
+
+    // for Chinese the CJK font comes first, until it stops claiming Chinese
+    families[1].borrow_mut().languages = vec![String::from("ja"), String::from("zh-cn")];
+    let first = |config: &Config, language| {
+        simulate(config, "sans-serif", "A", Some(language)).unwrap()[0]
+            .family
+            .map(|x| x.borrow().name.clone())
+    };
+    assert_eq!(first(&config, "zh-tw"), Some(String::from("Noto Sans CJK SC")));
+    assert_eq!(first(&config, "ko"), Some(String::from("DejaVu Sans")));
+    config.strip_language("Noto Sans CJK SC", "zh-cn").unwrap();
+    assert_eq!(first(&config, "zh-tw"), Some(String::from("DejaVu Sans")));
+    assert!(simulate(&config, "sans-serif", "A", Some("Chinese")).is_err());
+
+    // a language rule puts its families ahead of the prefer list
+    config.unstrip_language("Noto Sans CJK SC", "zh-cn").unwrap();
+    families[2].borrow_mut().languages = vec![String::from("zh-tw")];
+    config.add_language_rule("sans-serif", "zh").unwrap();
+    config
+        .insert_language_family("sans-serif", "zh", "Noto Sans CJK TC", None)
+        .unwrap();
+    assert_eq!(first(&config, "zh-tw"), Some(String::from("Noto Sans CJK TC")));
+    assert_eq!(first(&config, "ja"), Some(String::from("Noto Sans CJK SC")));
+}
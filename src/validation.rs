@@ -0,0 +1,479 @@
+use config::Config;
+use deserialization;
+use deserialization::{child_element, children_element};
+use family::Family;
+use itertools::Itertools;
+use range;
+use serialization;
+use sxd_document::dom::ChildOfElement;
+use sxd_document::dom::Element;
+use sxd_document::parser;
+
+use std::cell::RefCell;
+use std::fmt;
+
+#[cfg(test)]
+use alias::FamilyList;
+#[cfg(test)]
+use consts::{DEFAULT_FONTS_CONF, TYPEHOLDER_COMMENT_PREFIX};
+#[cfg(test)]
+use range::Range;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    /// fontconfig rejects the element, or typeholder cannot read the config back.
+    Error,
+    /// The config works, though likely not as meant.
+    Warning,
+}
+
+/// A problem found by `validate`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: {}", self.message),
+            Severity::Warning => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+enum Content {
+    Empty,
+    Text,
+    Elements(&'static [&'static str]),
+    Expressions,
+}
+
+const EXPRESSIONS: &[&str] = &[
+    "bool", "double", "int", "range", "matrix", "name", "string", "const", "charset", "langset",
+    "or", "and", "plus", "minus", "times", "divide", "not", "if", "floor", "ceil", "round",
+    "trunc", "eq", "not_eq", "less", "less_eq", "more", "more_eq", "contains", "not_contains",
+];
+
+// the content and attributes fonts.dtd allows for each element
+const ELEMENTS: &[(&str, Content, &[&str])] = &[
+    (
+        "fontconfig",
+        Content::Elements(&[
+            "dir", "cachedir", "include", "config", "selectfont", "match", "alias",
+            "description", "remap-dir", "reset-dirs",
+        ]),
+        &[],
+    ),
+    ("dir", Content::Text, &["prefix", "salt"]),
+    ("cachedir", Content::Text, &["prefix"]),
+    ("include", Content::Text, &["ignore_missing", "prefix", "deprecated"]),
+    ("description", Content::Text, &["domain"]),
+    ("remap-dir", Content::Text, &["as-path", "prefix", "salt"]),
+    ("reset-dirs", Content::Empty, &[]),
+    ("config", Content::Elements(&["blank", "rescan"]), &[]),
+    ("blank", Content::Elements(&["int", "range"]), &[]),
+    ("rescan", Content::Elements(&["int"]), &[]),
+    ("selectfont", Content::Elements(&["acceptfont", "rejectfont"]), &[]),
+    ("acceptfont", Content::Elements(&["glob", "pattern"]), &[]),
+    ("rejectfont", Content::Elements(&["glob", "pattern"]), &[]),
+    ("glob", Content::Text, &[]),
+    ("pattern", Content::Elements(&["patelt"]), &[]),
+    ("patelt", Content::Expressions, &["name"]),
+    ("alias", Content::Elements(&["family", "prefer", "accept", "default"]), &["binding"]),
+    ("prefer", Content::Elements(&["family"]), &[]),
+    ("accept", Content::Elements(&["family"]), &[]),
+    ("default", Content::Elements(&["family"]), &[]),
+    ("family", Content::Text, &[]),
+    ("match", Content::Elements(&["test", "edit"]), &["target"]),
+    ("test", Content::Expressions, &["qual", "name", "target", "compare"]),
+    ("edit", Content::Expressions, &["name", "mode", "binding"]),
+    ("int", Content::Text, &[]),
+    ("double", Content::Text, &[]),
+    ("string", Content::Text, &[]),
+    ("bool", Content::Text, &[]),
+    ("const", Content::Text, &[]),
+    ("name", Content::Text, &["target"]),
+    ("matrix", Content::Expressions, &[]),
+    ("range", Content::Elements(&["int"]), &[]),
+    ("charset", Content::Elements(&["int", "range"]), &[]),
+    ("langset", Content::Elements(&["string"]), &[]),
+    ("or", Content::Expressions, &[]),
+    ("and", Content::Expressions, &[]),
+    ("plus", Content::Expressions, &[]),
+    ("minus", Content::Expressions, &[]),
+    ("times", Content::Expressions, &[]),
+    ("divide", Content::Expressions, &[]),
+    ("not", Content::Expressions, &[]),
+    ("if", Content::Expressions, &[]),
+    ("floor", Content::Expressions, &[]),
+    ("ceil", Content::Expressions, &[]),
+    ("round", Content::Expressions, &[]),
+    ("trunc", Content::Expressions, &[]),
+    ("eq", Content::Expressions, &[]),
+    ("not_eq", Content::Expressions, &[]),
+    ("less", Content::Expressions, &[]),
+    ("less_eq", Content::Expressions, &[]),
+    ("more", Content::Expressions, &[]),
+    ("more_eq", Content::Expressions, &[]),
+    ("contains", Content::Expressions, &[]),
+    ("not_contains", Content::Expressions, &[]),
+];
+
+const REQUIRED_ATTRIBUTES: &[(&str, &str)] = &[
+    ("patelt", "name"),
+    ("test", "name"),
+    ("edit", "name"),
+    ("remap-dir", "as-path"),
+];
+
+// attributes fonts.dtd limits to a list of values
+const ATTRIBUTE_VALUES: &[(&str, &str, &[&str])] = &[
+    ("dir", "prefix", &["default", "xdg", "relative", "cwd"]),
+    ("cachedir", "prefix", &["default", "xdg", "relative", "cwd"]),
+    ("include", "prefix", &["default", "xdg", "relative", "cwd"]),
+    ("remap-dir", "prefix", &["default", "xdg", "relative", "cwd"]),
+    ("include", "ignore_missing", &["no", "yes"]),
+    ("include", "deprecated", &["no", "yes"]),
+    ("alias", "binding", &["weak", "strong", "same"]),
+    ("match", "target", &["pattern", "font", "scan"]),
+    ("test", "qual", &["any", "all", "first", "not_first"]),
+    ("test", "target", &["pattern", "font", "default"]),
+    (
+        "test",
+        "compare",
+        &["eq", "not_eq", "less", "less_eq", "more", "more_eq", "contains", "not_contains"],
+    ),
+    (
+        "edit",
+        "mode",
+        &[
+            "assign", "assign_replace", "prepend", "prepend_first", "append", "append_last",
+            "delete", "delete_all",
+        ],
+    ),
+    ("edit", "binding", &["weak", "strong", "same"]),
+    ("name", "target", &["default", "font", "pattern"]),
+];
+
+/// Checks `config` as it would be saved, see `validate`.
+pub fn validate_config(config: &Config) -> Vec<Diagnostic> {
+    match serialization::to_string(config) {
+        Ok(text) => validate(&text, config.families),
+        Err(err) => vec![error(err.to_string())],
+    }
+}
+
+/// Checks the fonts.conf `text` against fonts.dtd, then the blocks typeholder wrote in it:
+/// families that are not installed, empty prefer lists, code points beyond U+10FFFF, blocks
+/// written twice and anything else that keeps typeholder from reading them back.
+pub fn validate(text: &str, families: &Vec<RefCell<Family>>) -> Vec<Diagnostic> {
+    let package = match parser::parse(text) {
+        Ok(package) => package,
+        Err((position, errors)) => {
+            let err = deserialization::xml_error(text, position, errors);
+            return vec![error(err.to_string())];
+        }
+    };
+    let doc = package.as_document();
+    let mut diagnostics = vec![];
+    match deserialization::root_element(doc) {
+        Some(root) if root.name().local_part() == "fontconfig" => {
+            check_element(root, &mut diagnostics);
+            check_typeholder_blocks(root, families, &mut diagnostics);
+            // what only reading the blocks back finds, like malformed range comments
+            if !diagnostics.iter().any(|x| x.severity == Severity::Error) {
+                let placeholders = deserialization::placeholders(&[text], families);
+                if let Err(err) = deserialization::parse(text, families, &placeholders) {
+                    diagnostics.push(error(err.to_string()));
+                }
+            }
+        }
+        _ => diagnostics.push(error(String::from("There is no <fontconfig> element"))),
+    }
+    diagnostics
+}
+
+fn check_element(e: Element, diagnostics: &mut Vec<Diagnostic>) {
+    let name = e.name().local_part();
+    let (content, attributes) = match ELEMENTS.iter().find(|x| x.0 == name) {
+        Some(&(_, ref content, attributes)) => (content, attributes),
+        None => {
+            diagnostics.push(error(format!("<{}> is not a fontconfig element", name)));
+            return;
+        }
+    };
+
+    for attribute in e.attributes() {
+        let attribute_name = attribute.name().local_part();
+        let values = ATTRIBUTE_VALUES.iter().find(
+            |x| x.0 == name && x.1 == attribute_name,
+        );
+        if !attributes.contains(&attribute_name) {
+            diagnostics.push(error(
+                format!("<{}> takes no {} attribute", name, attribute_name),
+            ));
+        } else if let Some(&(_, _, values)) = values {
+            if !values.contains(&attribute.value()) {
+                diagnostics.push(error(format!(
+                    "{}=\"{}\" of <{}> is not one of {}",
+                    attribute_name,
+                    attribute.value(),
+                    name,
+                    values.join(", ")
+                )));
+            }
+        }
+    }
+    for &(_, attribute_name) in REQUIRED_ATTRIBUTES.iter().filter(|x| x.0 == name) {
+        if e.attribute_value(attribute_name).is_none() {
+            diagnostics.push(error(
+                format!("<{}> needs a {} attribute", name, attribute_name),
+            ));
+        }
+    }
+
+    for child in e.children() {
+        match child {
+            ChildOfElement::Element(x) => {
+                let child_name = x.name().local_part();
+                let allowed = match *content {
+                    Content::Elements(names) => names.contains(&child_name),
+                    Content::Expressions => EXPRESSIONS.contains(&child_name),
+                    Content::Empty | Content::Text => false,
+                };
+                if !allowed {
+                    diagnostics.push(error(format!("<{}> does not take <{}>", name, child_name)));
+                }
+                check_element(x, diagnostics);
+            }
+            ChildOfElement::Text(x) if !x.text().trim().is_empty() => {
+                match *content {
+                    Content::Text => {}
+                    _ => {
+                        diagnostics.push(error(
+                            format!("<{}> does not take text \"{}\"", name, x.text().trim()),
+                        ))
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if name == "range" && children_element("int", e).count() != 2 {
+        diagnostics.push(error(String::from("<range> takes two <int>")));
+    }
+    // ranges elsewhere may hold weights and the like
+    if name == "charset" || name == "blank" {
+        check_code_points(e, diagnostics);
+    }
+}
+
+fn check_code_points(e: Element, diagnostics: &mut Vec<Diagnostic>) {
+    let name = e.name().local_part();
+    let code_point = |int_elem: Element| {
+        let text = deserialization::text(int_elem).map_or("", |x| x.text().trim());
+        range::parse_code_point(text).ok_or_else(|| {
+            error(format!(
+                "<{}> holds {}, which is not a code point up to U+10FFFF",
+                name,
+                text
+            ))
+        })
+    };
+    for int_elem in children_element("int", e) {
+        if let Err(diagnostic) = code_point(int_elem) {
+            diagnostics.push(diagnostic);
+        }
+    }
+    for range_elem in children_element("range", e) {
+        let bounds = children_element("int", range_elem).map(&code_point).collect_vec();
+        match bounds.as_slice() {
+            &[Ok(first), Ok(last)] => {
+                if let Err(err) = range::bounds(first, last) {
+                    diagnostics.push(error(format!("<{}> holds {}", name, err)));
+                }
+            }
+            // a range without two ints is reported with the structure
+            _ => diagnostics.extend(bounds.into_iter().filter_map(Result::err)),
+        }
+    }
+}
+
+// what typeholder reads back other than fontconfig applies it: of two aliases with the same
+// name, typeholder edits only the first, and of two blocks stripping from the same family only
+// the last counts. Families that are not installed are kept, they are only worth a warning.
+fn check_typeholder_blocks(
+    root: Element,
+    families: &Vec<RefCell<Family>>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let is_installed = |name: &str| families.iter().any(|x| x.borrow().name == name);
+
+    let alias_elems = typeholder_blocks(root, "alias", None);
+    let alias_names = alias_elems
+        .iter()
+        .map(|&x| child_element("family", x).map_or("", text_of))
+        .collect_vec();
+    for (i, &alias_elem) in alias_elems.iter().enumerate() {
+        let alias = alias_names[i];
+        if alias_names[..i].contains(&alias) {
+            diagnostics.push(error(format!("Alias \"{}\" is written twice", alias)));
+        }
+        let prefer_elem = child_element("prefer", alias_elem);
+        if prefer_elem.map_or(true, |x| child_element("family", x).is_none()) {
+            diagnostics.push(warning(format!("Alias \"{}\" prefers no family", alias)));
+        }
+        for list in &["prefer", "accept", "default"] {
+            let list_elem = match child_element(list, alias_elem) {
+                Some(x) => x,
+                None => continue,
+            };
+            for name in children_element("family", list_elem).map(text_of) {
+                if !is_installed(name) && !alias_names.contains(&name) {
+                    diagnostics.push(warning(format!(
+                        "\"{}\" in the {} list of alias \"{}\" is not installed",
+                        name,
+                        list,
+                        alias
+                    )));
+                }
+            }
+        }
+    }
+
+    let mut scanned = vec![];
+    for match_elem in typeholder_blocks(root, "match", Some("scan")) {
+        let family = test_string(match_elem, "family");
+        if scanned.contains(&family) {
+            diagnostics.push(error(format!("The ranges of \"{}\" are written twice", family)));
+        } else if !is_installed(family) {
+            diagnostics.push(warning(format!(
+                "\"{}\" has stripped ranges but is not installed",
+                family
+            )));
+        }
+        scanned.push(family);
+    }
+
+    let mut rules = vec![];
+    for match_elem in typeholder_blocks(root, "match", Some("pattern")) {
+        let rule = (test_string(match_elem, "family"), test_string(match_elem, "lang"));
+        let (alias, language) = rule;
+        if rules.contains(&rule) {
+            diagnostics.push(error(
+                format!("The {} rule of alias \"{}\" is written twice", language, alias),
+            ));
+        } else if !alias_names.contains(&alias) {
+            diagnostics.push(error(format!(
+                "The {} rule of alias \"{}\" has no alias to go with",
+                language,
+                alias
+            )));
+        }
+        rules.push(rule);
+        let names = child_element("edit", match_elem)
+            .into_iter()
+            .flat_map(|x| children_element("string", x))
+            .map(text_of);
+        for name in names.filter(|x| !is_installed(x)) {
+            diagnostics.push(warning(format!(
+                "\"{}\" in the {} rule of alias \"{}\" is not installed",
+                name,
+                language,
+                alias
+            )));
+        }
+    }
+}
+
+fn typeholder_blocks<'d>(root: Element<'d>, name: &str, target: Option<&str>) -> Vec<Element<'d>> {
+    root.children()
+        .into_iter()
+        .filter_map(|x| x.element())
+        .filter(|&x| {
+            x.name().local_part() == name && x.attribute_value("target") == target &&
+                deserialization::prev_is_typeholder_comment(x)
+        })
+        .collect()
+}
+
+// the string a `<test name="...">` compares with
+fn test_string<'d>(e: Element<'d>, name: &str) -> &'d str {
+    children_element("test", e)
+        .find(|x| x.attribute_value("name") == Some(name))
+        .and_then(|x| child_element("string", x))
+        .map_or("", text_of)
+}
+
+fn text_of<'d>(e: Element<'d>) -> &'d str {
+    deserialization::text(e).map_or("", |x| x.text())
+}
+
+fn error(message: String) -> Diagnostic {
+    Diagnostic {
+        severity: Severity::Error,
+        message: message,
+    }
+}
+
+fn warning(message: String) -> Diagnostic {
+    Diagnostic {
+        severity: Severity::Warning,
+        message: message,
+    }
+}
+
+
+#[test]
+fn test_validate() {
+    let families = vec![RefCell::new(Family::new("DejaVu Sans"))];
+    let placeholders = vec![];
+    let mut config = deserialization::parse(DEFAULT_FONTS_CONF, &families, &placeholders).unwrap();
+    config
+        .insert_family("sans-serif", FamilyList::Prefer, "DejaVu Sans", None)
+        .unwrap();
+    config.remove_alias("monospace").unwrap();
+    config.remove_alias("serif").unwrap();
+    assert_eq!(validate_config(&config), vec![]);
+
+    let text = serialization::to_string(&config)
+        .unwrap()
+        .replace("<family>DejaVu Sans</family>", "<family>Gone Sans</family>")
+        .replace("</fontconfig>", "<match target=\"all\"><bogus/></match></fontconfig>");
+    let messages = validate(&text, &families).into_iter().map(|x| x.to_string()).collect_vec();
+    assert_eq!(messages.len(), 4);
+    assert_eq!(messages[0], "error: target=\"all\" of <match> is not one of pattern, font, scan");
+    assert_eq!(messages[1], "error: <match> does not take <bogus>");
+    assert_eq!(messages[2], "error: <bogus> is not a fontconfig element");
+    assert!(messages[3].starts_with("warning: \"Gone Sans\" in the prefer list"));
+
+    // typeholder edits only the first of two aliases with the same name
+    let text = serialization::to_string(&config).unwrap().replace(
+        "</fontconfig>",
+        &format!(
+            "<!--{}--><alias><family>sans-serif</family></alias></fontconfig>",
+            TYPEHOLDER_COMMENT_PREFIX
+        ),
+    );
+    let messages = validate(&text, &families).into_iter().map(|x| x.to_string()).collect_vec();
+    assert!(messages.contains(&String::from(
+        "error: Alias \"sans-serif\" is written twice",
+    )));
+
+    // range comments are only checked by reading the config back
+    config
+        .strip_range("DejaVu Sans", Range::custom("Quotes", 0x2018, 0x201f).unwrap())
+        .unwrap();
+    let text = serialization::to_string(&config)
+        .unwrap()
+        .replace("Quotes, Custom", "Quotes");
+    let messages = validate(&text, &families).into_iter().map(|x| x.to_string()).collect_vec();
+    assert_eq!(
+        messages,
+        vec!["error: Range comment \" Quotes \" is not in the form \"name, kind\""]
+    );
+}